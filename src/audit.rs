@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::io::Write;
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cli::APP;
+use crate::util::{fmt_count, show};
+use crate::{group_at_depth, user_name};
+
+/// Exit status when the audit finds any violation
+pub const AUDIT_EXIT: i32 = 4;
+
+const KINDS: [&str; 5] = ["world writable", "setuid", "setgid", "unreadable by owner", "device outside /dev"];
+const COLUMNS: [&str; 5] = ["world-w", "setuid", "setgid", "no-read", "device"];
+const WORLD_WRITABLE: u8 = 1;
+const SETUID: u8 = 1 << 1;
+const SETGID: u8 = 1 << 2;
+const OWNER_UNREADABLE: u8 = 1 << 3;
+const DEVICE: u8 = 1 << 4;
+
+/// Bit mask of the risky permission traits of an entry
+///
+/// Directories are only world writable when the sticky bit is also off,
+/// setuid and setgid only count on files someone can execute.
+#[cfg(target_family = "unix")]
+pub fn check(path: &Path, md: &Metadata) -> u8 {
+    let mode = md.permissions().mode();
+    let ft = md.file_type();
+    let mut v = 0;
+    if ft.is_dir() {
+        if mode & 0o002 != 0 && mode & 0o1000 == 0 {
+            v |= WORLD_WRITABLE;
+        }
+    } else if ft.is_file() {
+        if mode & 0o002 != 0 {
+            v |= WORLD_WRITABLE;
+        }
+        if mode & 0o111 != 0 {
+            if mode & 0o4000 != 0 {
+                v |= SETUID;
+            }
+            if mode & 0o2000 != 0 {
+                v |= SETGID;
+            }
+        }
+        if mode & 0o400 == 0 {
+            v |= OWNER_UNREADABLE;
+        }
+    } else if (ft.is_block_device() || ft.is_char_device()) && !under_dev(path) {
+        v |= DEVICE;
+    }
+    v
+}
+
+// the scanned path can reach /dev through .. or a symlinked directory
+#[cfg(target_family = "unix")]
+fn under_dev(path: &Path) -> bool {
+    match path.canonicalize() {
+        Ok(p) => p.starts_with("/dev"),
+        Err(_) => path.starts_with("/dev"),
+    }
+}
+
+#[cfg(target_family = "windows")]
+pub fn check(_path: &Path, _md: &Metadata) -> u8 {
+    0
+}
+
+#[cfg(target_family = "unix")]
+fn uid(md: &Metadata) -> u32 {
+    md.uid()
+}
+
+#[cfg(target_family = "windows")]
+fn uid(_md: &Metadata) -> u32 {
+    0
+}
+
+#[derive(Default)]
+pub struct AuditStats {
+    total: [u64; 5],
+    per_user: BTreeMap<u32, [u64; 5]>,
+    per_dir: BTreeMap<PathBuf, [u64; 5]>,
+    examples: [Vec<PathBuf>; 5],
+}
+
+impl AuditStats {
+    pub fn add(&mut self, path: &Path, md: &Metadata) {
+        let v = check(path, md);
+        if v == 0 {
+            return;
+        }
+        let u = self.per_user.entry(uid(md)).or_default();
+        let d = self.per_dir.entry(group_at_depth(path, md.is_dir(), 1)).or_default();
+        for i in 0..KINDS.len() {
+            if v & (1 << i) != 0 {
+                self.total[i] += 1;
+                u[i] += 1;
+                d[i] += 1;
+                if self.examples[i].len() < APP.limit {
+                    self.examples[i].push(path.to_path_buf());
+                }
+            }
+        }
+    }
+
+    pub fn violations(&self) -> u64 {
+        self.total.iter().sum()
+    }
+
+    pub fn print(&self, out: &mut dyn Write) -> Result<()> {
+        writeln!(out, "\nPermission audit: {} violations", self.violations())?;
+        for (i, k) in KINDS.iter().enumerate() {
+            writeln!(out, "{:>14} {}", fmt_count(self.total[i]), k)?;
+        }
+        if self.violations() == 0 {
+            return Ok(());
+        }
+        let header = COLUMNS.iter().map(|k| format!("{:>10}", k)).collect::<Vec<_>>().join(" ");
+        writeln!(out, "\nPermission audit per user:")?;
+        writeln!(out, "{:<14} {}", "", header)?;
+        for (uid, c) in self.per_user.iter() {
+            writeln!(out, "{:<14} {}", user_name(*uid), Self::counts(c))?;
+        }
+        writeln!(out, "\nPermission audit per top level directory:")?;
+        writeln!(out, "{}", header)?;
+        for (p, c) in self.per_dir.iter() {
+            writeln!(out, "{} {}", Self::counts(c), show(p))?;
+        }
+        for (i, k) in KINDS.iter().enumerate() {
+            if !self.examples[i].is_empty() {
+                writeln!(out, "\nAudit {}: {} of {}", k, self.examples[i].len(), self.total[i])?;
+                for p in self.examples[i].iter() {
+                    writeln!(out, "{:>14} {}", "", show(p))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn counts(c: &[u64; 5]) -> String {
+        c.iter().map(|x| format!("{:>10}", fmt_count(*x))).collect::<Vec<_>>().join(" ")
+    }
+}
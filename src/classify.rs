@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use regex::RegexSet;
+use serde::Deserialize;
+
+use crate::util::glob_to_re;
+
+// Built-in mapping of file name patterns to categories.  A user file
+// has the same layout and its patterns are always tried before these.
+const BUILTIN: &str = r#"
+[categories]
+media = ["*.jpg", "*.jpeg", "*.png", "*.gif", "*.bmp", "*.tif", "*.tiff", "*.heic", "*.webp", "*.svg",
+         "*.mp3", "*.flac", "*.wav", "*.ogg", "*.aac", "*.m4a",
+         "*.mp4", "*.mkv", "*.mov", "*.avi", "*.wmv", "*.webm", "*.mpg", "*.mpeg", "*.m4v"]
+archives = ["*.zip", "*.tar", "*.tgz", "*.tbz2", "*.txz", "*.gz", "*.bz2", "*.xz", "*.zst", "*.lz4", "*.7z", "*.rar",
+            "*.tar.gz", "*.tar.bz2", "*.tar.xz", "*.tar.zst", "*.tar.lz4", "*.jar", "*.war", "*.rpm", "*.deb"]
+logs = ["*.log", "*.log.[0-9]*", "*.log.gz", "*.log.zst", "*.log.[0-9]*.gz", "*.out", "*.err", "*.trace"]
+vm-images = ["*.iso", "*.img", "*.qcow", "*.qcow2", "*.vmdk", "*.vdi", "*.vhd", "*.vhdx", "*.ova", "*.ovf", "*.raw"]
+build-artifacts = ["*.o", "*.obj", "*.a", "*.so", "*.so.[0-9]*", "*.dll", "*.lib", "*.exe", "*.class", "*.pyc",
+                   "*.rlib", "*.rmeta", "*.d", "*.pdb", "*.whl", "*.egg"]
+data = ["*.parquet", "*.orc", "*.avro", "*.csv", "*.tsv", "*.json", "*.jsonl", "*.h5", "*.hdf5", "*.nc",
+        "*.npy", "*.npz", "*.safetensors", "*.pt", "*.pth", "*.ckpt", "*.onnx", "*.sqlite", "*.db"]
+documents = ["*.pdf", "*.doc", "*.docx", "*.xls", "*.xlsx", "*.ppt", "*.pptx", "*.odt", "*.ods", "*.txt", "*.md"]
+"#;
+
+#[derive(Deserialize)]
+struct ClassFile {
+    categories: BTreeMap<String, Vec<String>>,
+}
+
+/// Maps file names to categories using glob patterns
+///
+/// When several patterns match the user supplied one wins first, then
+/// the longest, so `*.log.gz` is a log and not an archive.
+#[derive(Debug, Clone)]
+pub struct Classifier {
+    set: RegexSet,
+    category: Vec<String>,
+}
+
+impl Classifier {
+    pub fn builtin() -> Classifier {
+        Self::build(&[], &Self::parse(BUILTIN).expect("built in classification is invalid"))
+            .expect("built in classification is invalid")
+    }
+
+    pub fn from_file(path: &Path) -> Result<Classifier> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read classification file: {}", path.display()))?;
+        let user = Self::parse(&s).with_context(|| format!("in classification file: {}", path.display()))?;
+        Self::build(&user, &Self::parse(BUILTIN)?)
+    }
+
+    fn parse(s: &str) -> Result<Vec<(String, String)>> {
+        let cf: ClassFile = toml::from_str(s)?;
+        let mut v = vec![];
+        for (cat, pats) in cf.categories {
+            for p in pats {
+                v.push((cat.clone(), p));
+            }
+        }
+        Ok(v)
+    }
+
+    fn build(user: &[(String, String)], builtin: &[(String, String)]) -> Result<Classifier> {
+        // a category named in the user file replaces the built in one of the same name
+        let mut all: Vec<(bool, &String, &String)> = user.iter().map(|x| (true, &x.0, &x.1)).collect();
+        all.extend(builtin.iter()
+            .filter(|b| !user.iter().any(|u| u.0 == b.0))
+            .map(|x| (false, &x.0, &x.1)));
+        all.sort_by(|a, b| b.0.cmp(&a.0).then(b.2.len().cmp(&a.2.len())));
+
+        let set = RegexSet::new(all.iter().map(|x| format!("(?i){}", glob_to_re(x.2))))?;
+        Ok(Classifier {
+            set,
+            category: all.iter().map(|x| x.1.clone()).collect(),
+        })
+    }
+
+    pub fn classify(&self, path: &Path) -> Option<&str> {
+        let name = path.file_name()?.to_str()?;
+        self.set.matches(name).iter().next().map(|i| self.category[i].as_str())
+    }
+}
@@ -6,6 +6,12 @@ use std::fs::symlink_metadata;
 use lazy_static::lazy_static;
 use std::cmp::max;
 use regex::Regex;
+use crate::classify::Classifier;
+use crate::compress::Compression;
+use crate::config;
+use crate::printf::{Columns, Template};
+use crate::quota::Quotas;
+use crate::util::{parse_size, Units};
 
 lazy_static! {
     pub static ref APP: ParLsCfg = {
@@ -44,7 +50,9 @@ lazy_static! {
 version = BUILD_INFO.as_str(), rename_all = "kebab-case",
 global_settings(& [
 structopt::clap::AppSettings::ColoredHelp,
-structopt::clap::AppSettings::UnifiedHelpMessage
+structopt::clap::AppSettings::UnifiedHelpMessage,
+// so the command line can override what the config files set
+structopt::clap::AppSettings::AllArgsOverrideSelf
 ]),
 )]
 pub struct ParLsCfg {
@@ -56,14 +64,315 @@ pub struct ParLsCfg {
     /// Write disk usage summary
     pub usage_mode: bool,
 
-    #[structopt(short = "l", name = "list-files")]
+    #[structopt(long = "units", value_name = "units", conflicts_with("block-size"))]
+    /// How sizes and counts are written in reports: iec, si or bytes
+    ///
+    /// iec, the default, scales sizes by 1024 as KiB, MiB and so on, si by 1000 as
+    /// kB, MB and so on, and bytes writes plain numbers with no separators for scripts.
+    units_given: Option<Units>,
+
+    #[structopt(skip)]
+    /// --units or blocks of --block-size
+    pub units: Units,
+
+    #[structopt(long = "block-size", parse(try_from_str = parse_block_size))]
+    /// Write report sizes as a count of blocks of this size, like 1K or 1M, rounded up
+    ///
+    /// Takes the place of --units, which cannot be given with it.  Counts keep
+    /// their thousands separators.
+    pub block_size: Option<u64>,
+
+    #[structopt(short = "l", long = "list-files", name = "list-files")]
     /// Write file list
     pub list_files: bool,
 
-    #[structopt(short = "n", name = "top-n-limit", default_value("10"))]
+    #[structopt(short = "n", long = "top-n-limit", name = "top-n-limit", default_value("10"))]
     /// Report top usage limit
     pub limit: usize,
 
+    #[structopt(long = "user-dir-matrix")]
+    /// Write a matrix of usage per user and top level directory
+    ///
+    /// Rows are the directories found at --matrix-depth below DIRECTORY,
+    /// and columns are the users owning space in them, with totals for both.
+    pub user_dir_matrix: bool,
+
+    #[structopt(long = "matrix-depth", default_value("1"))]
+    /// Depth below DIRECTORY of the directories used for the user matrix
+    pub matrix_depth: usize,
+
+    #[structopt(long = "top-per-dir")]
+    /// Write the top largest subdirectories and files under each directory at --top-per-dir-depth
+    ///
+    /// So one huge project does not crowd every other out of the overall top lists.
+    /// Directories with nothing under them are still listed, with no entries.
+    pub top_per_dir: bool,
+
+    #[structopt(long = "top-per-dir-depth", default_value("1"))]
+    /// Depth below DIRECTORY of the directories broken down by --top-per-dir
+    pub top_per_dir_depth: usize,
+
+    #[structopt(long = "ext-per-user")]
+    /// Write the top file extensions for each user
+    pub ext_per_user: bool,
+
+    #[structopt(long = "classify")]
+    /// Write usage per file category using the built in classification
+    pub classify: bool,
+
+    #[structopt(long = "classify-config", parse(try_from_str = parse_classify))]
+    /// TOML file of file name patterns per category - implies --classify
+    ///
+    /// The file holds a [categories] table of category names to lists of glob
+    /// patterns like "*.tar.zst", matched against file names ignoring case with
+    /// the same globs as --quota.  Its patterns are tried before the built in
+    /// ones, and a category named here replaces the built in category of the same name.
+    pub classifier: Option<Classifier>,
+
+    #[structopt(long = "sniff")]
+    /// Write usage per content type found by reading the first bytes of each file
+    ///
+    /// Detects gzip, zstd, ELF, PNG, JPEG, PDF, SQLite, HDF5, parquet and tar
+    /// by magic number.  This opens every file so it costs an extra read per file.
+    pub sniff: bool,
+
+    #[structopt(long = "dupes")]
+    /// Find files with duplicate content and write the sets wasting the most space - implies -u
+    ///
+    /// Files are grouped by size and only same size files are read and hashed,
+    /// first the head of each file and then the whole file when the heads match.
+    /// Hashing runs on --worker-threads threads once the scan is done.
+    pub dupes: bool,
+
+    #[structopt(long = "dupes-min-size", default_value("1"))]
+    /// Smallest file size in bytes considered for duplicates
+    pub dupes_min_size: u64,
+
+    #[structopt(long = "dupes-list")]
+    /// Write all duplicate sets to this file as delimited lines - implies --dupes
+    pub dupes_list: Option<PathBuf>,
+
+    #[structopt(long = "sparse-slack")]
+    /// Write sparse files and directories wasting the most block slack space
+    ///
+    /// Sparse files have less space allocated than their apparent size.  Slack
+    /// is allocation beyond the apparent size, like many tiny files on a
+    /// filesystem with large blocks.  Allocation is not known on windows.
+    pub sparse_slack: bool,
+
+    #[structopt(long = "sparse-min-gap", default_value("1M"), parse(try_from_str = parse_size))]
+    /// Smallest gap between apparent size and allocation for a file to be called sparse
+    ///
+    /// Takes a size like 64K or 1M.  Files a block or two short of their size,
+    /// from preallocation or compression, stay out of the sparse list.
+    pub sparse_min_gap: u64,
+
+    #[structopt(long = "empty")]
+    /// Write counts of zero byte files and recursively empty directories per top level directory
+    pub empty: bool,
+
+    #[structopt(long = "list-empty")]
+    /// List only zero byte files and the top most recursively empty directories - implies -l
+    ///
+    /// Empty directories are only known once the scan is done so the whole
+    /// list is written at the end.
+    pub list_empty: bool,
+
+    #[structopt(long = "print0", requires("list-empty"))]
+    /// With --list-empty write only the paths, each ending with a NUL
+    ///
+    /// A usage report then needs --report-output or --list-output so the two
+    /// do not end up mixed on stdout.
+    pub print0: bool,
+
+    #[structopt(long = "check-links")]
+    /// Check where each symbolic link leads and write counts of broken ones
+    ///
+    /// Links are still not followed for the scan, only their target is stat'ed
+    /// to find broken links, links that resolve outside DIRECTORY and links to
+    /// another filesystem.  The counts are part of the usage summary and the
+    /// links themselves are only listed with --list-links.
+    pub check_links: bool,
+
+    #[structopt(long = "list-links")]
+    /// List only broken, outside and other filesystem links - implies -l and --check-links
+    pub list_links: bool,
+
+    #[structopt(long = "audit")]
+    /// Write a permission audit and exit with status 4 when anything is flagged
+    ///
+    /// Flags world writable files and directories without the sticky bit,
+    /// setuid and setgid executables, files their owner cannot read and device
+    /// nodes outside /dev, summarised per user and per top level directory.
+    pub audit: bool,
+
+    #[structopt(long = "quota", parse(try_from_str = parse_quota))]
+    /// TOML file of size and file count limits to check after the scan
+    ///
+    /// Each [[limit]] table names one of path, user or group and any of
+    /// warn_size, crit_size, warn_files and crit_files.  Paths are globs matched
+    /// against each directory, relative to DIRECTORY unless they start with /,
+    /// where * stays within one name, ** crosses them and "." is DIRECTORY itself.
+    /// User and group take a name, an id or * for each.  Sizes are bytes or
+    /// strings like "50G", and files count regular files only.
+    /// Exits with status 5 when a warning limit is passed and 6 for a critical one.
+    pub quotas: Option<Quotas>,
+
+    #[structopt(long = "orphans")]
+    /// Write space owned by uids and gids with no account and their largest directories
+    pub orphans: bool,
+
+    #[structopt(long = "list-orphans")]
+    /// List only entries whose uid or gid has no account - implies -l
+    pub list_orphans: bool,
+
+    #[structopt(long = "prom-file")]
+    /// Write usage metrics in OpenMetrics text format to this file - implies -u
+    ///
+    /// Meant for the node_exporter textfile collector, so the file is written
+    /// to a temp file next to it and renamed into place.
+    pub prom_file: Option<PathBuf>,
+
+    #[structopt(long = "prom-depth", default_value("1"))]
+    /// Depth below DIRECTORY of directories given their own size metrics
+    pub prom_depth: usize,
+
+    #[structopt(long = "daemon", conflicts_with("list-files"))]
+    /// Keep running, rescanning on an interval and serving the latest results
+    ///
+    /// Plain HTTP GET is answered with JSON on /report for the usage report,
+    /// /size?path=DIR for one directory's totals and /status for the scan state.
+    /// Listens on 127.0.0.1:7311 unless --listen or --listen-unix is given.
+    pub daemon: bool,
+
+    #[structopt(long = "watch", conflicts_with("list-files"))]
+    /// After the scan keep directory totals current from inotify change events
+    ///
+    /// The report is printed again after each burst of changes, or served
+    /// when used with --daemon.  Only directory totals are kept up to date,
+    /// the other sections are as of the last full scan.  Falls back to
+    /// rescanning every --rescan-interval when the inotify watch limit is hit.
+    /// Linux only.
+    pub watch: bool,
+
+    #[structopt(long = "rescan-interval", parse(try_from_str = dur_from_str), default_value("1h"))]
+    /// Time to wait after a scan finishes before starting the next in daemon or watch mode
+    pub rescan_interval: Duration,
+
+    #[structopt(long = "listen")]
+    /// TCP address to serve daemon queries on, like 127.0.0.1:7311
+    pub listen: Option<String>,
+
+    #[structopt(long = "listen-unix")]
+    /// Unix socket path to serve daemon queries on
+    ///
+    /// A socket left at the path by an earlier run is replaced, anything else
+    /// there is an error.
+    pub listen_unix: Option<PathBuf>,
+
+    #[structopt(long = "config")]
+    /// TOML file of options read after /etc/du2.toml and ~/.config/du2/config.toml
+    ///
+    /// Keys are long option names like worker-threads = 8 or exclude-re = "...",
+    /// and [profile.NAME] tables hold sets of options chosen with --profile.
+    /// DU2_<OPTION> environment variables, like DU2_WORKER_THREADS=8, override
+    /// the files and the command line overrides them all, dropping any of
+    /// theirs it cannot be used with, like --units for --block-size.  DU2_CONFIG and
+    /// DU2_PROFILE stand in for --config and --profile, and other DU2_ names
+    /// that are not options are ignored.  A flag turned on by a file or the
+    /// environment is turned off again with --no-<flag>, like --no-sorted.
+    pub config: Option<PathBuf>,
+
+    #[structopt(long = "profile")]
+    /// Apply the options in the [profile.NAME] table of the config files
+    pub profile: Option<String>,
+
+    #[structopt(long = "report-output")]
+    /// Write the usage report and banners to this file instead of stdout - implies -u
+    ///
+    /// The file is written under a temp name and renamed into place once complete.
+    pub report_output: Option<PathBuf>,
+
+    #[structopt(long = "list-output")]
+    /// Write the file listing to this file instead of stdout - implies -l
+    ///
+    /// The file is written under a temp name and renamed into place once complete.
+    pub list_output: Option<PathBuf>,
+
+    #[structopt(long = "list-compress", requires("list-output"))]
+    /// Compress the --list-output file with gzip or zstd, or none
+    ///
+    /// Chosen from a file name ending in .gz or .zst when not given.
+    /// Compression runs on its own thread so traversal does not wait on it.
+    pub list_compress: Option<Compression>,
+
+    #[structopt(long = "list-parquet", conflicts_with_all(&["list-output", "list-empty", "list-links"]))]
+    /// Write the file listing to this Parquet file instead of as text - implies -l
+    ///
+    /// Columns are path, parent, name, extension, size, blocks, uid, gid, mode,
+    /// mtime, atime, ctime, inode and nlink, zstd compressed in row groups of
+    /// about a million rows.  The extension is the lower case one of the
+    /// extension report, like .tar.gz.
+    pub list_parquet: Option<PathBuf>,
+
+    #[structopt(long = "printf", conflicts_with_all(&["list-parquet", "print0"]))]
+    /// Write each listed entry with this template instead of the fixed columns - implies -l
+    ///
+    /// Like find -printf: %p path, %P path below DIRECTORY, %f name, %h parent,
+    /// %e lower case extension like .tar.gz, %y type, %s size, %b 512 byte blocks,
+    /// %k KB blocks, %u user, %U uid, %g group, %G gid, %m octal mode,
+    /// %M symbolic mode, %i inode, %n links, %D device, %d depth and %A, %B, %C
+    /// or %T for access, birth, change or modify time followed by @ for epoch
+    /// seconds or + for ISO 8601 in UTC.
+    /// A width like %10s or %-10s pads a field.  \n, \t and \0 are escapes and no
+    /// newline is added, e.g. "%y %s %u %g %m %T@ %p\n".
+    pub printf: Option<Template>,
+
+    #[structopt(long = "columns", conflicts_with_all(&["printf", "list-parquet", "print0"]))]
+    /// Extra columns added to the end of each listing line, comma separated
+    ///
+    /// Any of inode, dev, nlink, gid, group, blocks (512 byte), atime, ctime and
+    /// btime, the times in epoch seconds.  Birth time comes from statx on linux
+    /// and is left empty where the filesystem does not record it.
+    pub columns: Option<Columns>,
+
+    #[structopt(long = "sorted", conflicts_with("list-parquet"))]
+    /// Write the listing in path order so listings of the same tree compare equal
+    ///
+    /// Without it entries come in whatever order the threads read directories.
+    /// Entries beyond --sort-memory are sorted in runs written to --sort-dir
+    /// and merged at the end, so the whole listing arrives only once the scan is done.
+    pub sorted: bool,
+
+    #[structopt(long = "sort-memory", default_value("256M"), parse(try_from_str = parse_size))]
+    /// Memory for sorting the listing before runs are spilled to disk, like 64M or 2G
+    pub sort_memory: u64,
+
+    #[structopt(long = "sort-dir")]
+    /// Directory for the temp files of a sort too big for --sort-memory [default: the system temp directory]
+    pub sort_dir: Option<PathBuf>,
+
+    #[structopt(long = "sqlite")]
+    /// Write the scan to this SQLite database - implies -u
+    ///
+    /// Table files has every file and directory listed, dirs the totals of each
+    /// directory with the id of its parent and scan when and how it was run.
+    /// Times are seconds since the epoch.  Any existing file is replaced.
+    pub sqlite: Option<PathBuf>,
+
+    #[structopt(long = "relative")]
+    /// Write paths relative to DIRECTORY, which itself is written as "."
+    ///
+    /// Applies everywhere --rewrite-prefix does.
+    pub relative: bool,
+
+    #[structopt(long = "rewrite-prefix", conflicts_with("relative"), parse(try_from_str = parse_rewrite))]
+    /// Replace a leading path prefix in everything written, like /mnt/nfs=nfs://host/export
+    ///
+    /// Applies to the listing and report and to the Parquet, SQLite, metrics and
+    /// daemon outputs, so scans of one tree mounted in different places compare.
+    pub rewrite_prefix: Option<(PathBuf, String)>,
+
     #[structopt(short = "d", long = "delimiter", default_value("|"))]
     /// Disk usage mode - do not write the files found
     pub delimiter: char,
@@ -98,7 +407,7 @@ pub struct ParLsCfg {
     /// and files.  Paths are not canonicalized.
     pub exclude_re: Option<Regex>,
 
-    #[structopt(short = "v", parse(from_occurrences))]
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     /// Verbosity - use more than one v for greater detail
     pub verbose: usize,
 
@@ -137,12 +446,35 @@ fn parse_regex(str: &str) -> Result<Regex> {
     Ok(Regex::new(str)?)
 }
 
+fn parse_classify(str: &str) -> Result<Classifier> {
+    // clap only shows the top level message so flatten the whole chain into it
+    Classifier::from_file(&PathBuf::from(str)).map_err(|e| anyhow!("{:#}", e))
+}
+
+fn parse_quota(str: &str) -> Result<Quotas> {
+    Quotas::from_file(&PathBuf::from(str)).map_err(|e| anyhow!("{:#}", e))
+}
+
+fn parse_rewrite(str: &str) -> Result<(PathBuf, String)> {
+    match str.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((PathBuf::from(from), to.to_string())),
+        _ => Err(anyhow!("expected FROM=TO not \"{}\"", str)),
+    }
+}
+
 fn parse_timespec(str: &str) -> Result<SystemTime> {
     let dur = dur_from_str(str)?;
     let ret = SystemTime::now() - dur;
     Ok(ret)
 }
 
+fn parse_block_size(str: &str) -> Result<u64> {
+    match parse_size(str)? {
+        0 => Err(anyhow!("block size must be more than 0")),
+        b => Ok(b),
+    }
+}
+
 fn parse_timespec_duration(str: &str) -> Result<Duration> {
     let dur = dur_from_str(str)?;
     Ok(dur)
@@ -161,24 +493,82 @@ fn dir_check(s: &str) -> Result<PathBuf> {
 
 //noinspection ALL
 fn get_cli() -> ParLsCfg {
-    let mut cfg = ParLsCfg::from_args();
+    let args = match config::args() {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    match implied(ParLsCfg::from_iter(args)) {
+        Ok(cfg) => cfg,
+        Err(msg) => conflict(msg),
+    }
+}
+
+// options turned on by others, then the combinations only seen once those
+// are applied - split out so config can try a command line without exiting
+pub fn implied(mut cfg: ParLsCfg) -> Result<ParLsCfg, &'static str> {
     if cfg.no_threads == 0 {
         cfg.no_threads = max(num_cpus::get(), 4);
     }
+    if cfg.list_empty {
+        cfg.list_files = true;
+    }
+    if cfg.list_orphans {
+        cfg.list_files = true;
+    }
+    if cfg.list_links {
+        cfg.list_files = true;
+        cfg.check_links = true;
+    }
+    cfg.units = match (cfg.block_size, cfg.units_given) {
+        (Some(b), _) => Units::Block(b),
+        (None, Some(u)) => u,
+        (None, None) => Units::default(),
+    };
+    if cfg.list_output.is_some() || cfg.list_parquet.is_some() || cfg.printf.is_some() {
+        cfg.list_files = true;
+    }
+    if cfg.dupes_list.is_some() {
+        cfg.dupes = true;
+    }
+    if cfg.dupes || cfg.quotas.is_some() || cfg.report_output.is_some() || cfg.sqlite.is_some() || cfg.prom_file.is_some() || cfg.top_per_dir {
+        cfg.usage_mode = true;
+    }
     if !cfg.usage_mode && !cfg.list_files {
         cfg.usage_mode = true;
     }
+    if cfg.classify && cfg.classifier.is_none() {
+        cfg.classifier = Some(Classifier::builtin());
+    }
+    if cfg.daemon && cfg.listen.is_none() && cfg.listen_unix.is_none() {
+        cfg.listen = Some("127.0.0.1:7311".to_string());
+    }
     if cfg.t_status_on_key || cfg.t_status_interval {
         cfg.update_status = true;
     }
-    cfg
+    // the list modes above turn on -l after clap checked its conflicts
+    if (cfg.daemon || cfg.watch) && cfg.list_files {
+        return Err("--daemon and --watch cannot be used with -l or options that list files like --printf, --list-output, --list-parquet or --list-links");
+    }
+    // NUL terminated paths and the text report cannot share stdout
+    if cfg.print0 && cfg.usage_mode && cfg.report_output.is_none() && cfg.list_output.is_none() {
+        return Err("--print0 with a usage report needs --report-output or --list-output");
+    }
+    Ok(cfg)
+}
+
+// option combinations only seen after the implications above are applied
+fn conflict(msg: &str) -> ! {
+    structopt::clap::Error::with_description(msg, structopt::clap::ErrorKind::ArgumentConflict).exit()
 }
 
 pub fn dur_from_str(s: &str) -> Result<Duration> {
     let mut _tmp = String::new();
     let mut tot_secs = 0u64;
     for c in s.chars() {
-        if c >= '0' && c <= '9' { _tmp.push(c); } else {
+        if c.is_ascii_digit() { _tmp.push(c); } else {
             tot_secs += match c {
                 's' => _tmp.parse::<u64>()?,
                 'm' => _tmp.parse::<u64>()? * 60,
@@ -195,11 +585,32 @@ pub fn dur_from_str(s: &str) -> Result<Duration> {
 }
 
 fn get_exe_name() -> String {
-    std::env::args().nth(0).unwrap()
+    std::env::args().next().unwrap()
 }
 
 
 /*
 
 
- */
\ No newline at end of file
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_size_is_a_positive_size() {
+        assert_eq!(parse_block_size("4K").unwrap(), 4096);
+        assert!(parse_block_size("0").is_err());
+        assert!(parse_block_size("x").is_err());
+    }
+
+    #[test]
+    fn rewrite_is_from_equals_to() {
+        assert_eq!(parse_rewrite("/data=/mnt/nfs").unwrap(), (PathBuf::from("/data"), "/mnt/nfs".to_string()));
+        assert_eq!(parse_rewrite("/a=/b=c").unwrap(), (PathBuf::from("/a"), "/b=c".to_string()));
+        assert_eq!(parse_rewrite("/data=").unwrap(), (PathBuf::from("/data"), "".to_string()));
+        assert!(parse_rewrite("=/x").is_err());
+        assert!(parse_rewrite("/data").is_err());
+    }
+}
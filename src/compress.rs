@@ -0,0 +1,172 @@
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread::{spawn, JoinHandle};
+
+use anyhow::{anyhow, Result};
+
+use crate::cli::APP;
+use crate::util::Output;
+use crate::worker_queue::WorkerQueue;
+
+// size of the blocks handed to the compression thread and how many
+// may wait for it before the listing has to wait in turn
+const BLOCK: usize = 256 * 1024;
+const BLOCKS_QUEUED: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(anyhow!("unknown compression \"{}\", expected none, gzip or zstd", s)),
+        }
+    }
+}
+
+impl Compression {
+    // from the file name when not given outright
+    fn for_path(path: Option<&Path>) -> Compression {
+        if let Some(c) = APP.list_compress {
+            return c;
+        }
+        match path.and_then(|p| p.extension()).and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") | Some("zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Where the listing goes - compressed on a thread of its own when asked
+/// for so traversal does not wait on it
+pub enum ListOut {
+    Plain(Output),
+    Compressed(Compressor),
+}
+
+// what the compression thread is sent - the listing a block at a time,
+// then whether to finish the file or discard it
+#[derive(Clone)]
+enum Block {
+    Data(Vec<u8>),
+    End(bool),
+}
+
+pub struct Compressor {
+    buf: Vec<u8>,
+    q: WorkerQueue<Block>,
+    // taken by finish or drop, whichever comes first
+    h: Option<JoinHandle<Result<()>>>,
+}
+
+impl ListOut {
+    pub fn new(path: Option<&Path>) -> Result<ListOut> {
+        let out = Output::new(path)?;
+        let c = Compression::for_path(path);
+        if c == Compression::None {
+            return Ok(ListOut::Plain(out));
+        }
+        // more waiters allowed than pushers so a full queue blocks instead of failing
+        let q = WorkerQueue::new(2, BLOCKS_QUEUED);
+        let mut c_q = q.clone();
+        // a discarded file is left unfinished so Output removes its temp file
+        let h = spawn(move || match c {
+            Compression::Gzip => {
+                let mut enc = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+                if !pump(&mut c_q, &mut enc)? {
+                    return Ok(());
+                }
+                enc.finish()?.finish()
+            }
+            _ => {
+                let mut enc = zstd::Encoder::new(out, 0)?;
+                if !pump(&mut c_q, &mut enc)? {
+                    return Ok(());
+                }
+                enc.finish()?.finish()
+            }
+        });
+        Ok(ListOut::Compressed(Compressor { buf: Vec::with_capacity(BLOCK), q, h: Some(h) }))
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self {
+            ListOut::Plain(out) => out.finish(),
+            ListOut::Compressed(mut c) => c.end(true),
+        }
+    }
+}
+
+impl Compressor {
+    fn end(&mut self, keep: bool) -> Result<()> {
+        let h = match self.h.take() {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+        if keep && !self.buf.is_empty() {
+            self.q.push(Block::Data(std::mem::take(&mut self.buf)))?;
+        }
+        self.q.push(Block::End(keep))?;
+        h.join().map_err(|_| anyhow!("listing compression thread panicked"))?
+    }
+}
+
+impl Drop for Compressor {
+    // never finished so something failed - stop the thread and discard the file
+    fn drop(&mut self) {
+        let _ = self.end(false);
+    }
+}
+
+// keeps taking blocks after a write error so the listing never blocks on a
+// full queue, and says at the end whether the file is to be kept
+fn pump(q: &mut WorkerQueue<Block>, enc: &mut dyn Write) -> Result<bool> {
+    let mut res = Ok(());
+    loop {
+        match q.pop() {
+            Block::Data(block) => {
+                if res.is_ok() {
+                    res = enc.write_all(&block);
+                }
+            }
+            Block::End(keep) => {
+                res?;
+                return Ok(keep);
+            }
+        }
+    }
+}
+
+impl Write for ListOut {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ListOut::Plain(out) => out.write(data),
+            ListOut::Compressed(c) => {
+                c.buf.extend_from_slice(data);
+                if c.buf.len() >= BLOCK {
+                    let full = std::mem::replace(&mut c.buf, Vec::with_capacity(BLOCK));
+                    c.q.push(Block::Data(full)).map_err(|e| std::io::Error::other(e.to_string()))?;
+                }
+                Ok(data.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ListOut::Plain(out) => out.flush(),
+            ListOut::Compressed(_) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use structopt::clap::ErrorKind;
+use structopt::StructOpt;
+use toml::{Table, Value};
+
+use crate::cli::{self, ParLsCfg};
+
+// variables named DU2_<OPTION> set an option, except these two
+const ENV_PREFIX: &str = "DU2_";
+const ENV_CONFIG: &str = "DU2_CONFIG";
+const ENV_PROFILE: &str = "DU2_PROFILE";
+
+#[cfg(target_family = "unix")]
+fn default_files() -> Vec<PathBuf> {
+    let mut v = vec![PathBuf::from("/etc/du2.toml")];
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(d) => v.push(PathBuf::from(d).join("du2/config.toml")),
+        None => if let Some(h) = env::var_os("HOME") {
+            v.push(PathBuf::from(h).join(".config/du2/config.toml"));
+        },
+    }
+    v
+}
+
+#[cfg(target_family = "windows")]
+fn default_files() -> Vec<PathBuf> {
+    env::var_os("APPDATA").map(|d| PathBuf::from(d).join("du2\\config.toml")).into_iter().collect()
+}
+
+// value of an option on the real command line, before it is parsed
+fn pre_scan(argv: &[OsString], long: &str) -> Option<OsString> {
+    let eq = format!("{}=", long);
+    let mut it = argv.iter().skip(1);
+    while let Some(a) = it.next() {
+        let s = a.to_string_lossy();
+        if s == long {
+            return it.next().cloned();
+        }
+        if let Some(v) = s.strip_prefix(&eq) {
+            return Some(v.into());
+        }
+    }
+    None
+}
+
+// how an option is given - clap 2 has no public list of its arguments so
+// each name is tried out, first to see it is known at all, then with two
+// paths after it where an option takes the first as its value but a flag
+// leaves an unexpected second DIRECTORY
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Flag,
+    Opt,
+}
+
+fn kind(name: &str) -> Option<Kind> {
+    // anything else could be read by clap as more than a name, like a=b
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    let long = format!("--{}", name);
+    let fails = |args: &[&str]| ParLsCfg::clap().get_matches_from_safe(args).err().map(|e| e.kind);
+    if fails(&["du2", "/", &long]) == Some(ErrorKind::UnknownArgument) {
+        return None;
+    }
+    match fails(&["du2", &long, "/", "/"]) {
+        Some(ErrorKind::UnknownArgument) | Some(ErrorKind::HelpDisplayed) | Some(ErrorKind::VersionDisplayed) => Some(Kind::Flag),
+        _ => Some(Kind::Opt),
+    }
+}
+
+// a command line that parses, one that fails only because options cannot be
+// used together, or one with some other problem
+fn try_parse(args: &[OsString]) -> Result<(), ErrorKind> {
+    match ParLsCfg::from_iter_safe(args) {
+        Err(e) => Err(e.kind),
+        Ok(cfg) => cli::implied(cfg).map(|_| ()).map_err(|_| ErrorKind::ArgumentConflict),
+    }
+}
+
+/// Command line to parse - options from the config files, the chosen
+/// profile and the environment ahead of the real arguments so they win
+///
+/// Files are /etc/du2.toml, then ~/.config/du2/config.toml, then the
+/// --config file, each overriding the keys of the one before.  Keys are
+/// long option names, with _ or - between words, and [profile.NAME]
+/// tables hold options applied on top when --profile NAME is given.
+pub fn args() -> Result<Vec<OsString>> {
+    let argv: Vec<OsString> = env::args_os().collect();
+    let mut files: Vec<(PathBuf, bool)> = default_files().into_iter().map(|f| (f, false)).collect();
+    if let Some(f) = pre_scan(&argv, "--config").or_else(|| env::var_os(ENV_CONFIG)) {
+        files.push((PathBuf::from(f), true));
+    }
+    let profile = pre_scan(&argv, "--profile").or_else(|| env::var_os(ENV_PROFILE))
+        .map(|p| p.to_string_lossy().to_string());
+
+    let mut tables = vec![];
+    for (f, required) in files {
+        if !required && !f.exists() {
+            continue;
+        }
+        let t = read(&f)?;
+        tables.push((f, t));
+    }
+    merge(argv, tables, profile.as_deref(), env::vars().collect())
+}
+
+// the config tables in order, the profile and DU2_ variables turned into
+// options ahead of argv - a --no-FLAG in argv drops a FLAG set by them, as
+// does any option in argv that cannot be used with one of theirs
+fn merge(argv: Vec<OsString>, tables: Vec<(PathBuf, Table)>, profile: Option<&str>,
+         vars: Vec<(String, String)>) -> Result<Vec<OsString>> {
+    let mut opts = BTreeMap::new();
+    let mut profiles: BTreeMap<String, BTreeMap<String, Value>> = BTreeMap::new();
+    for (f, table) in tables.iter() {
+        for (k, v) in table {
+            match (k.as_str(), v) {
+                ("profile", Value::Table(t)) => {
+                    for (name, p) in t {
+                        let p = match p {
+                            Value::Table(p) => p,
+                            _ => return Err(anyhow!("profile.{} in {} is not a table", name, f.display())),
+                        };
+                        for (k, v) in p {
+                            if kind(&key(k)).is_none() {
+                                return Err(anyhow!("unknown option {} in profile.{} of {}", k, name, f.display()));
+                            }
+                            profiles.entry(name.clone()).or_default().insert(key(k), v.clone());
+                        }
+                    }
+                }
+                (_, v) => {
+                    if kind(&key(k)).is_none() {
+                        return Err(anyhow!("unknown option {} in {}", k, f.display()));
+                    }
+                    opts.insert(key(k), v.clone());
+                }
+            }
+        }
+    }
+    if let Some(name) = profile {
+        match profiles.remove(name) {
+            Some(p) => opts.extend(p),
+            None => return Err(anyhow!("no [profile.{}] in any of: {}", name,
+                                       tables.iter().map(|f| f.0.display().to_string()).collect::<Vec<_>>().join(", "))),
+        }
+    }
+    for (k, v) in vars {
+        if k == ENV_CONFIG || k == ENV_PROFILE {
+            continue;
+        }
+        // other programs may use the prefix too so only take our options
+        let name = match k.strip_prefix(ENV_PREFIX).map(key) {
+            Some(name) if kind(&name).is_some() => name,
+            _ => continue,
+        };
+        let v = if kind(&name) == Some(Kind::Flag) && name != "verbose" {
+            match v.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Value::Boolean(true),
+                "false" | "no" | "off" | "0" | "" => Value::Boolean(false),
+                _ => return Err(anyhow!("{} must be true or false not \"{}\"", k, v)),
+            }
+        } else {
+            Value::String(v)
+        };
+        opts.insert(name, v);
+    }
+
+    let mut rest = vec![];
+    for a in argv.iter().skip(1) {
+        match a.to_str().and_then(|s| s.strip_prefix("--no-")) {
+            Some(flag) if kind(flag) == Some(Kind::Flag) && kind(&format!("no-{}", flag)).is_none() => {
+                opts.remove(flag);
+            }
+            _ => rest.push(a.clone()),
+        }
+    }
+
+    let mut set = vec![];
+    for (k, v) in opts {
+        let a = match v {
+            Value::Boolean(true) => vec![format!("--{}", k).into()],
+            Value::Boolean(false) => continue,
+            // the one option that is a count of how often it is given
+            Value::Integer(n) if k == "verbose" => (0..n).map(|_| OsString::from("--verbose")).collect(),
+            Value::String(s) if k == "verbose" => {
+                let n: usize = s.parse().with_context(|| format!("verbose must be a number not \"{}\"", s))?;
+                (0..n).map(|_| OsString::from("--verbose")).collect()
+            }
+            Value::String(s) => vec![format!("--{}={}", k, s).into()],
+            Value::Integer(n) => vec![format!("--{}={}", k, n).into()],
+            Value::Float(n) => vec![format!("--{}={}", k, n).into()],
+            _ => return Err(anyhow!("option {} must be a string, number or boolean", k)),
+        };
+        set.push(a);
+    }
+
+    // repeats of an option already go to the last one given, but clap fails
+    // on a pair that conflicts, so leave out whatever the real command line
+    // conflicts with - unless it fails on its own and clap should say why
+    let line = |set: &[Vec<OsString>]| -> Vec<OsString> {
+        let mut out = vec![argv[0].clone()];
+        out.extend(set.iter().flatten().cloned());
+        out.extend(rest.iter().cloned());
+        out
+    };
+    if try_parse(&line(&[])).is_ok() {
+        set.retain(|a| try_parse(&line(std::slice::from_ref(a))) != Err(ErrorKind::ArgumentConflict));
+    }
+    Ok(line(&set))
+}
+
+fn key(k: &str) -> String {
+    k.to_lowercase().replace('_', "-")
+}
+
+fn read(path: &Path) -> Result<Table> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("cannot read config file: {}", path.display()))?;
+    toml::from_str(&s).with_context(|| format!("in config file: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(s: &str) -> Table {
+        toml::from_str(s).unwrap()
+    }
+
+    fn run(argv: &[&str], tables: &[(&str, &str)], profile: Option<&str>, vars: &[(&str, &str)]) -> Result<Vec<String>> {
+        let argv = argv.iter().map(OsString::from).collect();
+        let tables = tables.iter().map(|(f, t)| (PathBuf::from(f), table(t))).collect();
+        let vars = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let out = merge(argv, tables, profile, vars)?;
+        Ok(out.into_iter().map(|a| a.to_string_lossy().to_string()).collect())
+    }
+
+    #[test]
+    fn later_sources_override_earlier() {
+        let out = run(&["du2", "-t", "2", "/x"],
+                      &[("etc", "worker-threads = 4\ntop-n-limit = 5"), ("user", "worker_threads = 6\n[profile.big]\ntop_n_limit = 50")],
+                      Some("big"),
+                      &[("DU2_WORKER_THREADS", "8")]).unwrap();
+        assert_eq!(out, vec!["du2", "--top-n-limit=50", "--worker-threads=8", "-t", "2", "/x"]);
+    }
+
+    #[test]
+    fn unknown_env_vars_are_skipped() {
+        let out = run(&["du2"], &[], None, &[("DU2_FOO", "1"), ("DU2_CONFIG", "x"), ("DU2_SORTED", "yes")]).unwrap();
+        assert_eq!(out, vec!["du2", "--sorted"]);
+    }
+
+    #[test]
+    fn env_flags_take_booleans() {
+        let out = run(&["du2"], &[("etc", "sorted = true")], None, &[("DU2_SORTED", "0")]).unwrap();
+        assert_eq!(out, vec!["du2"]);
+        assert!(run(&["du2"], &[], None, &[("DU2_SORTED", "maybe")]).is_err());
+        let out = run(&["du2"], &[], None, &[("DU2_VERBOSE", "2")]).unwrap();
+        assert_eq!(out, vec!["du2", "--verbose", "--verbose"]);
+    }
+
+    #[test]
+    fn no_flag_on_the_command_line_drops_a_config_flag() {
+        let out = run(&["du2", "--no-sorted", "/x"], &[("etc", "sorted = true\ntop-n-limit = 5")], None, &[]).unwrap();
+        assert_eq!(out, vec!["du2", "--top-n-limit=5", "/x"]);
+        // only flags have a --no- form
+        let out = run(&["du2", "--no-top-n-limit"], &[("etc", "top-n-limit = 5")], None, &[]).unwrap();
+        assert_eq!(out, vec!["du2", "--top-n-limit=5", "--no-top-n-limit"]);
+    }
+
+    #[test]
+    fn command_line_wins_over_a_conflicting_option() {
+        let etc = [("etc", "units = \"si\"\nrelative = true\ndaemon = true\ntop-n-limit = 5")];
+        let out = run(&["du2", "--block-size", "1K", "/"], &etc[..1], None, &[]).unwrap();
+        assert_eq!(out, vec!["du2", "--daemon", "--relative", "--top-n-limit=5", "--block-size", "1K", "/"]);
+        let out = run(&["du2", "--rewrite-prefix", "/=x", "-l", "/"], &etc, None, &[]).unwrap();
+        assert_eq!(out, vec!["du2", "--top-n-limit=5", "--units=si", "--rewrite-prefix", "/=x", "-l", "/"]);
+        // conflicts that only show once the implied options are on
+        let out = run(&["du2", "--printf", "%p", "/"], &[], None, &[("DU2_WATCH", "yes")]).unwrap();
+        assert_eq!(out, vec!["du2", "--printf", "%p", "/"]);
+        // a command line that is wrong by itself is left for clap to report
+        let out = run(&["du2", "--units", "si", "--block-size", "1K", "/"], &etc[..1], None, &[]).unwrap();
+        assert_eq!(&out[1..4], ["--daemon", "--relative", "--top-n-limit=5"]);
+    }
+
+    #[test]
+    fn flags_and_options_by_trying_them() {
+        assert_eq!(kind("sorted"), Some(Kind::Flag));
+        assert_eq!(kind("verbose"), Some(Kind::Flag));
+        assert_eq!(kind("units"), Some(Kind::Opt));
+        assert_eq!(kind("top-n-limit"), Some(Kind::Opt));
+        assert_eq!(kind("delimiter"), Some(Kind::Opt));
+        // a flag that requires another
+        assert_eq!(kind("print0"), Some(Kind::Flag));
+        assert_eq!(kind("bogus"), None);
+        assert_eq!(kind("units=si"), None);
+        assert_eq!(kind(""), None);
+    }
+
+    #[test]
+    fn bad_config_is_an_error() {
+        assert!(run(&["du2"], &[("etc", "bogus = 1")], None, &[]).is_err());
+        assert!(run(&["du2"], &[("etc", "[profile.a]\nbogus = 1")], None, &[]).is_err());
+        assert!(run(&["du2"], &[("etc", "top-n-limit = 5")], Some("missing"), &[]).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::BinaryHeap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+#[cfg(target_family = "unix")]
+use std::os::unix::net::UnixListener;
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::cli::{APP, EXE};
+use crate::tstatus::{ThreadStatus, ThreadTracker};
+use crate::util::{show, unshow};
+#[cfg(target_os = "linux")]
+use crate::watch::{watch, WatchEnd};
+use crate::{scan, to_sort_vec, to_sort_vec_file_ext, user_name, AllStats, TrackedPath};
+
+struct DaemonState {
+    latest: Option<Arc<RwLock<AllStats>>>,
+    tracker: ThreadTracker,
+    scanning: bool,
+    scans: u64,
+    last_start: Option<SystemTime>,
+    last_secs: f64,
+    last_error: Option<String>,
+}
+
+type Shared = Arc<Mutex<DaemonState>>;
+
+// most requests served at once - any more are turned away with a 503
+const MAX_HANDLERS: usize = 32;
+// a client that stops sending or reading is dropped after this long
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+// bound on the request line and headers read from one client
+const MAX_REQUEST: u64 = 64 * 1024;
+
+/// Rescan forever on the configured interval, serving the latest results
+///
+/// Answers plain HTTP GET on a TCP address and/or unix socket:
+/// /report for the usage report, /size?path=P for one directory's
+/// totals and /status for the state of the scan and its threads.
+pub fn run(main_status: &mut ThreadStatus) -> Result<i32> {
+    let shared: Shared = Arc::new(Mutex::new(DaemonState {
+        latest: None,
+        tracker: ThreadTracker::new(),
+        scanning: false,
+        scans: 0,
+        last_start: None,
+        last_secs: 0.0,
+        last_error: None,
+    }));
+
+    let active = Arc::new(AtomicUsize::new(0));
+    if let Some(addr) = &APP.listen {
+        let l = TcpListener::bind(addr).with_context(|| format!("cannot listen on {}", addr))?;
+        eprintln!("{}: daemon listening on http://{}", *EXE, addr);
+        let (shared, active) = (shared.clone(), active.clone());
+        thread::spawn(move || {
+            for s in l.incoming().flatten() {
+                if s.set_read_timeout(Some(IO_TIMEOUT)).and(s.set_write_timeout(Some(IO_TIMEOUT))).is_ok() {
+                    spawn_handler(s, &shared, &active);
+                }
+            }
+        });
+    }
+    #[cfg(target_family = "unix")]
+    if let Some(path) = &APP.listen_unix {
+        // a socket left over from a previous run would make bind fail but
+        // anything else at the path is not ours to remove
+        match std::fs::symlink_metadata(path) {
+            Ok(md) if md.file_type().is_socket() => {
+                std::fs::remove_file(path).with_context(|| format!("cannot remove old socket {}", path.display()))?;
+            }
+            Ok(_) => return Err(anyhow!("{} exists and is not a socket", path.display())),
+            Err(_) => {}
+        }
+        let l = UnixListener::bind(path).with_context(|| format!("cannot listen on {}", path.display()))?;
+        eprintln!("{}: daemon listening on unix socket {}", *EXE, path.display());
+        let (shared, active) = (shared.clone(), active.clone());
+        thread::spawn(move || {
+            for s in l.incoming().flatten() {
+                if s.set_read_timeout(Some(IO_TIMEOUT)).and(s.set_write_timeout(Some(IO_TIMEOUT))).is_ok() {
+                    spawn_handler(s, &shared, &active);
+                }
+            }
+        });
+    }
+
+    let mut watching = APP.watch;
+    loop {
+        let mut tt = ThreadTracker::new();
+        {
+            let mut st = shared.lock().unwrap();
+            st.scanning = true;
+            st.tracker = tt.clone();
+            st.last_start = Some(SystemTime::now());
+        }
+        main_status.set_state("scanning");
+        let start = Instant::now();
+        let res = scan(&mut tt, main_status);
+        let mut fresh = None;
+
+        let mut st = shared.lock().unwrap();
+        st.scanning = false;
+        st.last_secs = start.elapsed().as_secs_f64();
+        match res {
+            Ok((stats, _)) => {
+                let stats = Arc::new(RwLock::new(stats));
+                fresh = Some(stats.clone());
+                st.latest = Some(stats);
+                st.scans += 1;
+                st.last_error = None;
+            }
+            Err(e) => {
+                eprintln!("{}: scan failed: {:#}", *EXE, e);
+                st.last_error = Some(format!("{:#}", e));
+            }
+        }
+        drop(st);
+        if let (true, Some(stats)) = (watching, fresh) {
+            main_status.set_state("watching for changes");
+            if watch_latest(&stats, &mut watching) {
+                continue;
+            }
+        }
+        main_status.set_state("waiting to rescan");
+        thread::sleep(APP.rescan_interval);
+    }
+}
+
+// keep the tree just scanned current in place so queries see changes -
+// true when it needs a rescan right away
+#[cfg(target_os = "linux")]
+fn watch_latest(stats: &Arc<RwLock<AllStats>>, watching: &mut bool) -> bool {
+    match watch(stats, &mut |_, _| Ok(())) {
+        Ok(WatchEnd::Resync) => true,
+        Ok(WatchEnd::LimitReached) => {
+            *watching = false;
+            false
+        }
+        Err(e) => {
+            eprintln!("{}: watching failed: {:#}", *EXE, e);
+            *watching = false;
+            false
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_latest(_stats: &Arc<RwLock<AllStats>>, watching: &mut bool) -> bool {
+    eprintln!("{}: --watch uses inotify and is only supported on linux", *EXE);
+    *watching = false;
+    false
+}
+
+// one thread per request up to MAX_HANDLERS
+fn spawn_handler<S: Read + Write + Send + 'static>(s: S, shared: &Shared, active: &Arc<AtomicUsize>) {
+    if active.fetch_add(1, Ordering::SeqCst) >= MAX_HANDLERS {
+        active.fetch_sub(1, Ordering::SeqCst);
+        let _ = respond(s, 503, &json!({"error": "too many requests in progress"}));
+        return;
+    }
+    let (shared, active) = (shared.clone(), active.clone());
+    thread::spawn(move || {
+        handle(s, &shared);
+        active.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+fn handle<S: Read + Write>(s: S, shared: &Shared) {
+    if let Err(e) = serve(s, shared) {
+        if APP.verbose > 0 {
+            eprintln!("{}: daemon request failed: {:#}", *EXE, e);
+        }
+    }
+}
+
+fn serve<S: Read + Write>(s: S, shared: &Shared) -> Result<()> {
+    let mut rdr = BufReader::new(s.take(MAX_REQUEST));
+    let mut line = String::new();
+    rdr.read_line(&mut line)?;
+    // headers are of no interest but must be read past
+    loop {
+        let mut h = String::new();
+        if rdr.read_line(&mut h)? == 0 || h.trim().is_empty() {
+            break;
+        }
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+
+    let (code, body) = if method != "GET" {
+        (405, json!({"error": "only GET is supported"}))
+    } else {
+        route(target, shared)
+    };
+    respond(rdr.into_inner().into_inner(), code, &body)
+}
+
+fn respond<S: Write>(mut s: S, code: u16, body: &Value) -> Result<()> {
+    let body = serde_json::to_string_pretty(body)?;
+    let reason = match code {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    write!(s, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           code, reason, body.len(), body)?;
+    s.flush()?;
+    Ok(())
+}
+
+fn route(target: &str, shared: &Shared) -> (u16, Value) {
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], &target[i + 1..]),
+        None => (target, ""),
+    };
+    if path == "/status" {
+        return (200, status_json(&shared.lock().unwrap()));
+    }
+    let latest = shared.lock().unwrap().latest.clone();
+    let stats = match (path, latest) {
+        ("/report", Some(s)) | ("/size", Some(s)) => s,
+        ("/report", None) | ("/size", None) => return (503, json!({"error": "no scan has finished yet"})),
+        _ => return (404, json!({"error": "unknown path, try /report, /size?path=DIR or /status"})),
+    };
+    let stats = stats.read().unwrap();
+    if path == "/report" {
+        return (200, report_json(&stats));
+    }
+    match query_param(query, "path") {
+        None => (404, json!({"error": "/size needs a path parameter"})),
+        // the path as the other outputs show it, so map it back to the scanned one
+        Some(p) => match stats.dtree.get(&unshow(&p)) {
+            None => (404, json!({"error": "directory not in the scanned tree", "path": p})),
+            Some(d) => (200, json!({
+                "path": p,
+                "size_recursive": d.size_recursively,
+                "files_recursive": d.file_count_recursively,
+                "dirs_recursive": d.dir_count_recursively,
+                "size_direct": d.size_directly,
+                "files_direct": d.file_count_directly,
+                "dirs_direct": d.dir_count_directly,
+            })),
+        },
+    }
+}
+
+fn status_json(st: &DaemonState) -> Value {
+    let threads: Vec<Value> = st.tracker.status_list().into_iter()
+        .map(|(name, tid, state)| json!({"name": name, "tid": tid, "state": state}))
+        .collect();
+    json!({
+        "root": show(&APP.dir),
+        "scanning": st.scanning,
+        "scans_completed": st.scans,
+        "last_scan_start": st.last_start.and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+        "last_scan_seconds": st.last_secs,
+        "last_error": st.last_error,
+        "threads": threads,
+    })
+}
+
+fn tracked(heap: &BinaryHeap<TrackedPath>) -> Value {
+    Value::Array(to_sort_vec(heap).into_iter()
+        .map(|v| json!({"path": show(&v.path), "value": v.size}))
+        .collect())
+}
+
+fn report_json(stats: &AllStats) -> Value {
+    let mut users: Vec<(&u32, &(u64, u64))> = stats.user_map.iter().collect();
+    users.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(b.0)));
+    let exts: Vec<Value> = to_sort_vec_file_ext(&stats.top_ext).into_iter().map(|v| {
+        let st = &stats.extensions[&v.extension];
+        json!({"extension": v.extension, "bytes": st.size, "files": st.count, "average": st.average(), "largest": st.largest})
+    }).collect();
+    json!({
+        "root": show(&APP.dir),
+        "total_bytes": stats.total_usage,
+        "users": users.iter().map(|(uid, c)| json!({"user": user_name(**uid), "uid": uid, "bytes": c.1, "files": c.0})).collect::<Vec<_>>(),
+        "top_dir_direct_bytes": tracked(&stats.top_dir),
+        "top_dir_recursive_bytes": tracked(&stats.top_dir_overall),
+        "top_dir_recursive_files": tracked(&stats.top_cnt_overall),
+        "top_dir_direct_files": tracked(&stats.top_cnt_file),
+        "top_dir_direct_dirs": tracked(&stats.top_cnt_dir),
+        "top_files_bytes": tracked(&stats.top_files),
+        "top_extensions": exts,
+    })
+}
+
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| pct_decode(v))
+}
+
+fn pct_decode(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        match b[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < b.len() => {
+                match std::str::from_utf8(&b[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(v) => { out.push(v); i += 2; }
+                    None => out.push(b'%'),
+                }
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
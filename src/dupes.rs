@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread::{spawn, JoinHandle};
+
+use anyhow::{Context, Result};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::cli::{APP, EXE};
+use crate::util::{show, write_atomic};
+use crate::worker_queue::WorkerQueue;
+
+// bytes hashed in the first pass - most files of equal size differ early
+const PARTIAL_LEN: u64 = 16 * 1024;
+
+#[derive(Debug)]
+pub struct DupeSet {
+    pub size: u64,
+    pub paths: Vec<PathBuf>,
+}
+
+impl DupeSet {
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+// (dev, inode) of a file where the platform has them
+type FileId = Option<(u64, u64)>;
+
+/// Files seen during the scan grouped by size
+///
+/// The file id is (dev, inode) where known so hard links to the same
+/// file are not counted as duplicates of each other.
+#[derive(Default)]
+pub struct SizeGroups {
+    by_size: BTreeMap<u64, Vec<(PathBuf, FileId)>>,
+}
+
+impl SizeGroups {
+    pub fn add(&mut self, path: &Path, size: u64, id: FileId) {
+        if size >= APP.dupes_min_size {
+            self.by_size.entry(size).or_default().push((path.to_path_buf(), id));
+        }
+    }
+}
+
+// size group, file and how much of its head to hash
+type HashJob = (usize, PathBuf, Option<u64>);
+
+fn hash_file(path: &Path, limit: Option<u64>) -> Result<u128> {
+    let f = File::open(path).with_context(|| format!("cannot open for hash: {}", path.display()))?;
+    let mut rdr: Box<dyn Read> = match limit {
+        Some(l) => Box::new(f.take(l)),
+        None => Box::new(f),
+    };
+    let mut h = Xxh3::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = rdr.read(&mut buf).with_context(|| format!("cannot read for hash: {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        h.update(&buf[..n]);
+    }
+    Ok(h.digest128())
+}
+
+// -t worker threads that hash files - built once and shared by the
+// partial and the full pass
+struct HashPool {
+    q: WorkerQueue<Option<HashJob>>,
+    results: Receiver<Option<(usize, PathBuf, u128)>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl HashPool {
+    fn new() -> HashPool {
+        let q: WorkerQueue<Option<HashJob>> = WorkerQueue::new(APP.no_threads, 0);
+        let (tx, results) = channel();
+        let mut handles = vec![];
+        for _ in 0..APP.no_threads {
+            let mut q = q.clone();
+            let tx = tx.clone();
+            handles.push(spawn(move || {
+                while let Some((grp, path, limit)) = q.pop() {
+                    let res = match hash_file(&path, limit) {
+                        Err(e) => {
+                            eprintln!("{}: {:#}", *EXE, e);
+                            None
+                        }
+                        Ok(h) => Some((grp, path, h)),
+                    };
+                    if tx.send(res).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        HashPool { q, results, handles }
+    }
+
+    // hash every job returning those that could be read
+    fn hash_all(&mut self, jobs: Vec<(usize, PathBuf)>, limit: Option<u64>) -> Result<Vec<(usize, PathBuf, u128)>> {
+        let n = jobs.len();
+        for (grp, path) in jobs {
+            self.q.push(Some((grp, path, limit)))?;
+        }
+        let mut v = Vec::with_capacity(n);
+        for _ in 0..n {
+            if let Some(x) = self.results.recv()? {
+                v.push(x);
+            }
+        }
+        Ok(v)
+    }
+
+    fn finish(mut self) -> Result<()> {
+        for _ in 0..self.handles.len() {
+            self.q.push(None)?;
+        }
+        for h in self.handles {
+            h.join().expect("Cannot join a hash thread");
+        }
+        Ok(())
+    }
+}
+
+// regroup hashed files by (group, hash) keeping only groups with more than one member
+fn collisions(hashed: Vec<(usize, PathBuf, u128)>) -> Vec<(usize, Vec<PathBuf>)> {
+    let mut m: BTreeMap<(usize, u128), Vec<PathBuf>> = BTreeMap::new();
+    for (grp, path, h) in hashed {
+        m.entry((grp, h)).or_default().push(path);
+    }
+    m.into_iter().filter(|x| x.1.len() > 1).map(|x| ((x.0).0, x.1)).collect()
+}
+
+/// Find sets of files with identical content, largest waste first
+///
+/// Only sizes shared by more than one file are read.  A partial hash
+/// of the head of each file is done first and the full file is only
+/// hashed when that collides.
+pub fn find_dupes(groups: &SizeGroups) -> Result<Vec<DupeSet>> {
+    let mut sizes = vec![];
+    let mut jobs = vec![];
+    for (size, files) in groups.by_size.iter() {
+        let mut seen = BTreeSet::new();
+        let mut distinct = vec![];
+        for (path, id) in files {
+            if id.is_none_or(|id| seen.insert(id)) {
+                distinct.push(path.clone());
+            }
+        }
+        if distinct.len() > 1 {
+            for p in distinct {
+                jobs.push((sizes.len(), p));
+            }
+            sizes.push(*size);
+        }
+    }
+    if APP.verbose > 0 {
+        eprintln!("{}: dupes: {} candidate files in {} size groups", *EXE, jobs.len(), sizes.len());
+    }
+
+    let mut sets = vec![];
+    let mut full_jobs = vec![];
+    let mut pool = HashPool::new();
+    for (grp, paths) in collisions(pool.hash_all(jobs, Some(PARTIAL_LEN))?) {
+        if sizes[grp] <= PARTIAL_LEN {
+            // the partial hash already covered the whole file
+            sets.push(DupeSet { size: sizes[grp], paths });
+        } else {
+            full_jobs.extend(paths.into_iter().map(|p| (grp, p)));
+        }
+    }
+    for (grp, paths) in collisions(pool.hash_all(full_jobs, None)?) {
+        sets.push(DupeSet { size: sizes[grp], paths });
+    }
+    pool.finish()?;
+    for s in sets.iter_mut() {
+        s.paths.sort();
+    }
+    sets.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then(a.paths.cmp(&b.paths)));
+    Ok(sets)
+}
+
+/// Write every duplicate set as delimited lines of set number, size, wasted bytes and path
+pub fn write_dupes_list(path: &Path, sets: &[DupeSet]) -> Result<()> {
+    write_atomic(path, |w| {
+        let d = APP.delimiter;
+        writeln!(w, "set{}size{}wasted{}path", d, d, d)?;
+        for (i, s) in sets.iter().enumerate() {
+            for p in s.paths.iter() {
+                writeln!(w, "{}{}{}{}{}{}{}", i, d, s.size, d, s.wasted(), d, show(p))?;
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,63 @@
+use std::fs::{canonicalize, metadata, read_link, Metadata};
+use std::io::Write;
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+
+use crate::cli::APP;
+use crate::util::show;
+
+pub const BROKEN: &str = "broken";
+pub const OTHER_FS: &str = "other-fs";
+pub const OUTSIDE: &str = "outside";
+pub const OK: &str = "ok";
+
+lazy_static! {
+    // links are resolved fully so the root must be too for the comparison
+    static ref CANON_ROOT: PathBuf = canonicalize(&APP.dir).unwrap_or_else(|_| APP.dir.clone());
+}
+
+/// Classify a symlink by where it leads
+///
+/// A link is broken when its target cannot be stat'ed, including loops.
+/// Otherwise it is other-fs when the target is on a different device than
+/// the link, outside when it resolves to somewhere not under the scanned
+/// root and ok in all other cases.
+pub fn check_link(path: &Path, link_md: &Metadata) -> &'static str {
+    let target_md = match metadata(path) {
+        Err(_) => return BROKEN,
+        Ok(md) => md,
+    };
+    if other_fs(link_md, &target_md) {
+        return OTHER_FS;
+    }
+    match canonicalize(path) {
+        Err(_) => BROKEN,
+        Ok(real) if !real.starts_with(&*CANON_ROOT) => OUTSIDE,
+        Ok(_) => OK,
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn other_fs(link_md: &Metadata, target_md: &Metadata) -> bool {
+    link_md.dev() != target_md.dev()
+}
+
+#[cfg(target_family = "windows")]
+fn other_fs(_link_md: &Metadata, _target_md: &Metadata) -> bool {
+    false
+}
+
+pub fn write_link_header(out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "link_state{}path{}target", APP.delimiter, APP.delimiter)?;
+    Ok(())
+}
+
+pub fn write_link(out: &mut dyn Write, path: &Path, state: &str) -> Result<()> {
+    let target = read_link(path).map(|t| t.to_string_lossy().to_string()).unwrap_or_default();
+    writeln!(out, "{}{}{}{}{}", state, APP.delimiter, show(path), APP.delimiter, target)?;
+    Ok(())
+}
@@ -1,15 +1,21 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
+use std::borrow::Cow;
+#[cfg(target_family = "unix")]
+use std::cell::RefCell;
 use std::cmp::max;
 use std::collections::{BinaryHeap, BTreeMap};
+#[cfg(target_family = "unix")]
+use std::collections::HashMap;
 use std::fs::{FileType, Metadata, symlink_metadata};
+use std::io::Write;
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
 #[cfg(target_family = "windows")]
 use std::os::windows::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::thread::spawn;
@@ -19,43 +25,69 @@ use std::time::SystemTime;
 use cpu_time::ProcessTime;
 use structopt::StructOpt;
 #[cfg(target_family = "unix")]
-use users::{get_current_uid, get_user_by_uid};
+use users::{get_current_uid, get_group_by_gid, get_user_by_uid};
 
 use anyhow::{anyhow, Context, Result};
 use lazy_static::lazy_static;
-use util::greek;
 use worker_queue::*;
 
 use crate::tstatus::{ThreadStatus, ThreadTracker, spawn_death_timeout_thread};
-use crate::util::multi_extension;
+use crate::util::{ext_key, fmt_count, fmt_size, show, Output};
+use crate::compress::ListOut;
+use crate::pq::ParquetList;
+use crate::sort::{entry, Sorter};
+use crate::sqlite::SqliteOut;
+use crate::sniff::sniff;
+use crate::audit::AuditStats;
+use crate::orphans::OrphanStats;
+use crate::dupes::{DupeSet, SizeGroups, find_dupes, write_dupes_list};
 use crate::cli::{APP,EXE};
 
 mod tstatus;
 mod worker_queue;
 mod util;
 mod cli;
-
+mod classify;
+mod compress;
+mod config;
+mod sniff;
+mod dupes;
+mod links;
+mod audit;
+mod orphans;
+mod pq;
+mod printf;
+mod prom;
+mod quota;
+mod sort;
+mod sqlite;
+mod daemon;
+#[cfg(target_os = "linux")]
+mod watch;
+
+// one directory's worth of entries plus a note from the reader thread:
+// the sniffed content type of a file or the state of a symlink, if asked for
+type MetaList = Vec<(PathBuf, Metadata, Option<&'static str>)>;
 
 fn main() {
-    if let Err(err) = parls() {
-        eprintln!("ERROR in main: {}", &err);
-        std::process::exit(11);
+    match parls() {
+        Err(err) => {
+            eprintln!("ERROR in main: {}", &err);
+            std::process::exit(11);
+        }
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
     }
 }
 
 
 //noinspection ALL
-fn read_dir_thread(queue: &mut WorkerQueue<Option<PathBuf>>, out_q: &mut WorkerQueue<Option<Vec<(PathBuf, Metadata)>>>, t_status: &mut ThreadStatus) {
+fn read_dir_thread(queue: &mut WorkerQueue<Option<PathBuf>>, out_q: &mut WorkerQueue<Option<MetaList>>, t_status: &mut ThreadStatus) {
     // get back to work slave loop....
     let t_cpu_time = cpu_time::ThreadTime::now();
-    loop {
-        match _read_dir_worker(queue, out_q, t_status) {
-            Err(e) => {
-                // filthy filthy error catch
-                eprintln!("{}: major error: {}  cause: {}", *EXE, e, e.root_cause());
-            }
-            Ok(()) => break,
-        }
+    while let Err(e) = _read_dir_worker(queue, out_q, t_status) {
+        // filthy filthy error catch
+        eprintln!("{}: major error: {}  cause: {}", *EXE, e, e.root_cause());
     }
 
     if APP.write_thread_cpu_time {
@@ -64,7 +96,7 @@ fn read_dir_thread(queue: &mut WorkerQueue<Option<PathBuf>>, out_q: &mut WorkerQ
 }
 
 //noinspection ALL
-fn _read_dir_worker(queue: &mut WorkerQueue<Option<PathBuf>>, out_q: &mut WorkerQueue<Option<Vec<(PathBuf, Metadata)>>>, t_status: &mut ThreadStatus) -> Result<()> {
+fn _read_dir_worker(queue: &mut WorkerQueue<Option<PathBuf>>, out_q: &mut WorkerQueue<Option<MetaList>>, t_status: &mut ThreadStatus) -> Result<()> {
     let mut pops_done = 0;
     t_status.register("started");
     loop {
@@ -78,79 +110,13 @@ fn _read_dir_worker(queue: &mut WorkerQueue<Option<PathBuf>>, out_q: &mut Worker
                 if APP.verbose > 1 {
                     if p.to_str().is_none() { break; } else { eprintln!("{}: listing for {}", *EXE, p.to_str().unwrap()); }
                 }
-                let mut other_dirs = vec![];
-                let mut metalist = vec![];
                 if APP.update_status {
                     t_status.set_state(&format!("at {} pops; reading dir: {}", pops_done, p.display()));
                 }
-                let dir_itr = match std::fs::read_dir(&p) {
-                    Err(e) => {
-                        eprintln!("{}: stat of dir: '{}', error: {}", *EXE, p.display(), e);
-                        continue;
-                    }
-                    Ok(i) => i,
+                let (metalist, other_dirs) = match list_dir(&p)? {
+                    None => continue,
+                    Some(x) => x,
                 };
-                'ENTRY_LOOP: for entry in dir_itr {
-                    let entry = entry?;
-                    let path = entry.path();
-                    let md = match symlink_metadata(&entry.path()) {
-                        Err(e) => {
-                            eprintln!("{}: stat of file for symlink: '{}', error: {}", *EXE, p.display(), e);
-                            continue;
-                        }
-                        Ok(md) => md,
-                    };
-                    if APP.verbose > 3 {
-                        eprintln!("{}: raw meta: {:#?}", *EXE, &md);
-                    }
-
-                    //
-                    // re filters
-                    //
-                    if let Some(re) = &APP.re {
-                        let s = path.to_str().unwrap();
-                        if !re.is_match(path.to_str().unwrap()) {
-                            if APP.verbose > 1 {
-                                eprintln!("{}: keep RE: filtered file not matching RE: \'{}\' file: {}", *EXE, &re.as_str(), s);
-                            }
-                            continue 'ENTRY_LOOP;
-                        }
-                        if APP.verbose > 1 {
-                            eprintln!("{}: keep RE: Unfiltered, file DOES match  RE: \'{}\' file: {}", *EXE, &re.as_str(),s);
-                        }
-                    }
-                    if let Some(re) = &APP.exclude_re {
-                        let s = path.to_str().unwrap();
-                        if re.is_match(path.to_str().unwrap()) {
-                            if APP.verbose > 1 {
-                                eprintln!("{}: exclude re: Filtered path matching RE: \'{}\' file: {}", *EXE, &re.as_str(),s);
-                            }
-                            continue 'ENTRY_LOOP;
-                        }
-                        if APP.verbose > 1 {
-                            eprintln!("{}: exclude re: Unfiltered file DOES NOT match  RE: \'{}\' file: {}", *EXE, &re.as_str(), s);
-                        }
-                    }
-
-                    let file_type: FileType = md.file_type();
-                    if !file_type.is_symlink() {
-                        if file_type.is_file() {
-                            //
-                            // age filters
-                            //
-                            let f_age = md.modified()?;
-                            if APP.file_newer_than.map_or(true, |x| x < f_age) && APP.file_older_than.map_or(true, |x| x > f_age) {
-                                metalist.push((path.clone(), md.clone()));
-                                //write_meta(&path, &md);
-                            }
-                        } else if file_type.is_dir() {
-                            metalist.push((path.clone(), md));
-                            other_dirs.push(path);
-                        }
-                    } else {
-                        if APP.verbose > 0 { eprintln!("{}: skipping sym link: {}", *EXE, path.to_string_lossy()); }
-                    }
-                }
 
                 if APP.update_status {
                     t_status.set_state(&format!("push meta, at {} pops", pops_done));
@@ -172,11 +138,97 @@ fn _read_dir_worker(queue: &mut WorkerQueue<Option<PathBuf>>, out_q: &mut Worker
     Ok(())
 }
 
+// read one directory applying the path and age filters - the entries kept
+// and the sub directories to descend into, or None if it cannot be read
+fn list_dir(p: &Path) -> Result<Option<(MetaList, Vec<PathBuf>)>> {
+    let mut other_dirs = vec![];
+    let mut metalist = vec![];
+    let dir_itr = match std::fs::read_dir(p) {
+        Err(e) => {
+            eprintln!("{}: stat of dir: '{}', error: {}", *EXE, p.display(), e);
+            return Ok(None);
+        }
+        Ok(i) => i,
+    };
+    'ENTRY_LOOP: for entry in dir_itr {
+        let entry = entry?;
+        let path = entry.path();
+        let md = match symlink_metadata(entry.path()) {
+            Err(e) => {
+                eprintln!("{}: stat of file for symlink: '{}', error: {}", *EXE, p.display(), e);
+                continue;
+            }
+            Ok(md) => md,
+        };
+        if APP.verbose > 3 {
+            eprintln!("{}: raw meta: {:#?}", *EXE, &md);
+        }
+
+        //
+        // re filters
+        //
+        if let Some(re) = &APP.re {
+            let s = path.to_str().unwrap();
+            if !re.is_match(path.to_str().unwrap()) {
+                if APP.verbose > 1 {
+                    eprintln!("{}: keep RE: filtered file not matching RE: \'{}\' file: {}", *EXE, &re.as_str(), s);
+                }
+                continue 'ENTRY_LOOP;
+            }
+            if APP.verbose > 1 {
+                eprintln!("{}: keep RE: Unfiltered, file DOES match  RE: \'{}\' file: {}", *EXE, &re.as_str(),s);
+            }
+        }
+        if let Some(re) = &APP.exclude_re {
+            let s = path.to_str().unwrap();
+            if re.is_match(path.to_str().unwrap()) {
+                if APP.verbose > 1 {
+                    eprintln!("{}: exclude re: Filtered path matching RE: \'{}\' file: {}", *EXE, &re.as_str(),s);
+                }
+                continue 'ENTRY_LOOP;
+            }
+            if APP.verbose > 1 {
+                eprintln!("{}: exclude re: Unfiltered file DOES NOT match  RE: \'{}\' file: {}", *EXE, &re.as_str(), s);
+            }
+        }
+
+        let file_type: FileType = md.file_type();
+        if !file_type.is_symlink() {
+            if file_type.is_file() {
+                //
+                // age filters
+                //
+                let f_age = md.modified()?;
+                if APP.file_newer_than.is_none_or(|x| x < f_age) && APP.file_older_than.is_none_or(|x| x > f_age) {
+                    let kind = if APP.sniff { Some(sniff(&path, md.len())) } else { None };
+                    metalist.push((path.clone(), md.clone(), kind));
+                    //write_meta(&path, &md);
+                }
+            } else if file_type.is_dir() {
+                metalist.push((path.clone(), md, None));
+                other_dirs.push(path);
+            } else if APP.audit {
+                // devices and such are only of interest to the audit
+                metalist.push((path, md, None));
+            }
+        } else if APP.check_links {
+            let state = links::check_link(&path, &md);
+            metalist.push((path, md, Some(state)));
+        } else {
+            if APP.verbose > 0 { eprintln!("{}: skipping sym link: {}", *EXE, path.to_string_lossy()); }
+        }
+    }
+    Ok(Some((metalist, other_dirs)))
+}
+
 // TODO: add username and or id
 // get windows user id / name?  how?  up to snuff here with unix
 
 #[cfg(target_family = "unix")]
-fn write_meta(path: &PathBuf, meta: &Metadata) -> Result<()> {
+fn write_meta(out: &mut dyn Write, path: &Path, meta: &Metadata) -> Result<()> {
+    if let Some(t) = &APP.printf {
+        return t.write(out, path, meta);
+    }
     let file_type = match meta.file_type() {
         x if x.is_file() => 'f',
         x if x.is_dir() => 'd',
@@ -185,44 +237,63 @@ fn write_meta(path: &PathBuf, meta: &Metadata) -> Result<()> {
     };
     match get_user_by_uid(meta.uid()) {
         None => {
-            println!("{}{}{}{}{}{}{:o}{}{}{}{}", file_type, APP.delimiter, path.to_string_lossy(),
-                     APP.delimiter, meta.size(), APP.delimiter, meta.permissions().mode(), APP.delimiter,
-                     meta.uid(), APP.delimiter, meta.modified()?.duration_since(SystemTime::UNIX_EPOCH)?.as_secs());
+            write!(out, "{}{}{}{}{}{}{:o}{}{}{}{}", file_type, APP.delimiter, show(path),
+                        APP.delimiter, meta.size(), APP.delimiter, meta.permissions().mode(), APP.delimiter,
+                        meta.uid(), APP.delimiter, meta.modified()?.duration_since(SystemTime::UNIX_EPOCH)?.as_secs())?;
         }
         Some(user) => {
-            println!("{}{}{}{}{}{}{:o}{}{}{}{}", file_type, APP.delimiter, path.to_string_lossy(),
-                     APP.delimiter, meta.size(), APP.delimiter, meta.permissions().mode(), APP.delimiter,
-                     user.name().to_string_lossy(), APP.delimiter, meta.modified()?.duration_since(SystemTime::UNIX_EPOCH)?.as_secs());
+            write!(out, "{}{}{}{}{}{}{:o}{}{}{}{}", file_type, APP.delimiter, show(path),
+                        APP.delimiter, meta.size(), APP.delimiter, meta.permissions().mode(), APP.delimiter,
+                        user.name().to_string_lossy(), APP.delimiter, meta.modified()?.duration_since(SystemTime::UNIX_EPOCH)?.as_secs())?;
         }
     };
+    if let Some(c) = &APP.columns {
+        c.write(out, path, meta)?;
+    }
+    writeln!(out)?;
     Ok(())
 }
 
 #[cfg(target_family = "unix")]
-fn write_meta_header() {
-    println!("{}{}{}{}{}{}{}{}{}{}{}", "type", APP.delimiter, "path",
-             APP.delimiter, "size", APP.delimiter, "permissions", APP.delimiter,
-             "user", APP.delimiter,
-             "epoch_last_modification");
+fn write_meta_header(out: &mut dyn Write) -> Result<()> {
+    write!(out, "type{}path{}size{}permissions{}user{}epoch_last_modification", APP.delimiter,
+                APP.delimiter, APP.delimiter, APP.delimiter, APP.delimiter)?;
+    if let Some(c) = &APP.columns {
+        c.write_header(out)?;
+    }
+    writeln!(out)?;
+    Ok(())
 }
 
 #[cfg(target_family = "windows")]
-fn write_meta_header() {
-    println!("{}{}{}{}{}{}{}{}{}", "type", APP.delimiter, "path",
-             APP.delimiter, "size", APP.delimiter, "readonly", APP.delimiter,
-             "epoch_last_modification");
+fn write_meta_header(out: &mut dyn Write) -> Result<()> {
+    write!(out, "{}{}{}{}{}{}{}{}{}", "type", APP.delimiter, "path",
+                APP.delimiter, "size", APP.delimiter, "readonly", APP.delimiter,
+                "epoch_last_modification")?;
+    if let Some(c) = &APP.columns {
+        c.write_header(out)?;
+    }
+    writeln!(out)?;
+    Ok(())
 }
 #[cfg(target_family = "windows")]
-fn write_meta(path: &PathBuf, meta: &Metadata) -> Result<()> {
+fn write_meta(out: &mut dyn Write, path: &Path, meta: &Metadata) -> Result<()> {
+    if let Some(t) = &APP.printf {
+        return t.write(out, path, meta);
+    }
     let file_type = match meta.file_type() {
         x if x.is_file() => 'f',
         x if x.is_dir() => 'd',
         x if x.is_symlink() => 's',
         _ => 'N',
     };
-    println!("{}{}{}{}{}{}{}{}{}", file_type, APP.delimiter, path.display(),
-             APP.delimiter, meta.len(), APP.delimiter, meta.permissions().readonly(), APP.delimiter,
-             meta.modified()?.duration_since(SystemTime::UNIX_EPOCH)?.as_secs());
+    write!(out, "{}{}{}{}{}{}{}{}{}", file_type, APP.delimiter, show(path),
+                APP.delimiter, meta.len(), APP.delimiter, meta.permissions().readonly(), APP.delimiter,
+                meta.modified()?.duration_since(SystemTime::UNIX_EPOCH)?.as_secs())?;
+    if let Some(c) = &APP.columns {
+        c.write(out, path, meta)?;
+    }
+    writeln!(out)?;
     Ok(())
 }
 
@@ -282,6 +353,12 @@ pub struct AgeRange {
     newest_file_recursive: Option<Duration>,
 }
 
+impl Default for AgeRange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AgeRange {
     pub fn new() -> Self {
         AgeRange{
@@ -292,12 +369,12 @@ impl AgeRange {
         }
     }
     pub fn update_direct(&mut self, new: &Duration) {
-        Self::max_age(&mut self.oldest_file_direct, &new);
-        Self::min_age(&mut self.newest_file_direct, &new);
+        Self::max_age(&mut self.oldest_file_direct, new);
+        Self::min_age(&mut self.newest_file_direct, new);
     }
     pub fn update_recursive(&mut self, new: &Duration) {
-        Self::max_age(&mut self.oldest_file_recursive, &new);
-        Self::min_age(&mut self.newest_file_recursive, &new);
+        Self::max_age(&mut self.oldest_file_recursive, new);
+        Self::min_age(&mut self.newest_file_recursive, new);
     }
     fn min_age(store: &mut Option<Duration>, new: &Duration) {
         match store {
@@ -327,13 +404,32 @@ struct DirStats {
     file_count_recursively: u64,
     dir_count_directly: u64,
     dir_count_recursively: u64,
+    slack_directly: u64,
     age_range: AgeRange,
 }
 
 
 impl DirStats {
     pub fn new() -> Self {
-        DirStats { size_recursively: 0, size_directly: 0, file_count_recursively: 0, file_count_directly: 0, dir_count_directly: 0, dir_count_recursively: 0 , age_range: AgeRange::new(), }
+        DirStats { size_recursively: 0, size_directly: 0, file_count_recursively: 0, file_count_directly: 0, dir_count_directly: 0, dir_count_recursively: 0, slack_directly: 0, age_range: AgeRange::new(), }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ExtStats {
+    size: u64,
+    count: u64,
+    largest: u64,
+}
+
+impl ExtStats {
+    fn add(&mut self, size: u64) {
+        self.size += size;
+        self.count += 1;
+        self.largest = max(self.largest, size);
+    }
+    fn average(&self) -> u64 {
+        self.size.checked_div(self.count).unwrap_or(0)
     }
 }
 
@@ -345,43 +441,105 @@ struct UserUsage {
 
 struct AllStats {
     dtree: BTreeMap<PathBuf, DirStats>,
-    extensions: BTreeMap<String, u64>,
+    extensions: BTreeMap<String, ExtStats>,
+    user_ext_map: BTreeMap<u32, BTreeMap<String, ExtStats>>,
+    categories: BTreeMap<String, ExtStats>,
+    content_types: BTreeMap<&'static str, ExtStats>,
+    size_groups: SizeGroups,
+    dupes: Vec<DupeSet>,
     user_map: BTreeMap<u32, (u64, u64)>,
+    group_map: BTreeMap<u32, (u64, u64)>,
+    // regular files only, as quota file limits count them
+    user_files: BTreeMap<u32, u64>,
+    group_files: BTreeMap<u32, u64>,
+    user_dir_map: BTreeMap<PathBuf, BTreeMap<u32, (u64, u64)>>,
     top_dir: BinaryHeap<TrackedPath>,
     top_cnt_dir: BinaryHeap<TrackedPath>,
     top_cnt_file: BinaryHeap<TrackedPath>,
     top_cnt_overall: BinaryHeap<TrackedPath>,
     top_dir_overall: BinaryHeap<TrackedPath>,
     top_files: BinaryHeap<TrackedPath>,
+    top_sparse: BinaryHeap<TrackedPath>,
+    top_slack_dir: BinaryHeap<TrackedPath>,
+    // largest subdirectories and files under each directory at --top-per-dir-depth
+    group_tops: BTreeMap<PathBuf, (BinaryHeap<TrackedPath>, BinaryHeap<TrackedPath>)>,
+    total_slack: u64,
+    empty_files: Vec<(PathBuf, Metadata)>,
+    empty_dirs: Vec<PathBuf>,
+    empty_per_dir: BTreeMap<PathBuf, (u64, u64)>,
+    link_states: BTreeMap<&'static str, u64>,
+    bad_link_dirs: BTreeMap<PathBuf, (u64, u64, u64)>,
+    audit: AuditStats,
+    orphans: OrphanStats,
     top_ext: BinaryHeap<TrackedExtension>,
     total_usage: u64,
+    // entries seen and the real and cpu time the scan took
+    scanned: (usize, Duration, Duration),
+    // file ages are measured back from here
+    started: SystemTime,
+}
+
+impl AllStats {
+    fn new() -> Self {
+        AllStats {
+            dtree: BTreeMap::new(),
+            extensions: BTreeMap::new(),
+            user_ext_map: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            content_types: BTreeMap::new(),
+            size_groups: SizeGroups::default(),
+            dupes: vec![],
+            top_files: BinaryHeap::new(),
+            top_sparse: BinaryHeap::new(),
+            top_slack_dir: BinaryHeap::new(),
+            group_tops: BTreeMap::new(),
+            total_slack: 0,
+            empty_files: vec![],
+            empty_dirs: vec![],
+            empty_per_dir: BTreeMap::new(),
+            link_states: BTreeMap::new(),
+            bad_link_dirs: BTreeMap::new(),
+            audit: AuditStats::default(),
+            orphans: OrphanStats::default(),
+            top_dir: BinaryHeap::new(),
+            top_cnt_dir: BinaryHeap::new(),
+            top_cnt_file: BinaryHeap::new(),
+            top_cnt_overall: BinaryHeap::new(),
+            top_dir_overall: BinaryHeap::new(),
+            top_ext: BinaryHeap::new(),
+            user_map: BTreeMap::new(),
+            group_map: BTreeMap::new(),
+            user_files: BTreeMap::new(),
+            group_files: BTreeMap::new(),
+            user_dir_map: BTreeMap::new(),
+            total_usage: 0u64,
+            scanned: (0, Duration::ZERO, Duration::ZERO),
+            started: SystemTime::now(),
+        }
+    }
 }
 
 //noinspection ALL
-fn track_top_n_ext(heap: &mut BinaryHeap<TrackedExtension>, ext: &String, s: u64, limit: usize) {
+fn track_top_n_ext(heap: &mut BinaryHeap<TrackedExtension>, ext: &str, s: u64, limit: usize) {
     if limit > 0 {
         if heap.len() < limit {
-            heap.push(TrackedExtension { size: s, extension: ext.clone() });
-            return;
+            heap.push(TrackedExtension { size: s, extension: ext.to_string() });
         } else if heap.peek().expect("internal error: cannot peek when the size is greater than 0!?").size < s {
             heap.pop();
-            heap.push(TrackedExtension { size: s, extension: ext.clone() });
-            return;
+            heap.push(TrackedExtension { size: s, extension: ext.to_string() });
         }
     }
 }
 
 //noinspection ALL
-fn track_top_n(heap: &mut BinaryHeap<TrackedPath>, p: &PathBuf, s: u64, limit: usize) {
+fn track_top_n(heap: &mut BinaryHeap<TrackedPath>, p: &Path, s: u64, limit: usize) {
 
     if limit > 0 {
         if heap.len() < limit {
-            heap.push(TrackedPath { size: s, path: p.clone() });
-            return;
+            heap.push(TrackedPath { size: s, path: p.to_path_buf() });
         } else if heap.peek().expect("internal error: cannot peek when the size is greater than 0!?").size < s {
             heap.pop();
-            heap.push(TrackedPath { size: s, path: p.clone() });
-            return;
+            heap.push(TrackedPath { size: s, path: p.to_path_buf() });
         }
     }
 }
@@ -405,7 +563,7 @@ fn _child_is_higher_than_base_dir(base: &Path, child: &Path) -> Result<bool> {
 }
 
 fn child_is_higher_than_base_dir(base: &Path, child: &Path) -> Result<bool> {
-    if child.starts_with(&base) {
+    if child.starts_with(base) {
         Ok(false)
     } else {
         eprintln!("base_s: {}  child_s: {}", base.display(), child.display());
@@ -413,16 +571,83 @@ fn child_is_higher_than_base_dir(base: &Path, child: &Path) -> Result<bool> {
     }
 }
 
+// the directory at depth levels below the top that this entry is grouped under
+// entries above that depth land in the nearest directory containing them
+fn group_at_depth(path: &Path, is_dir: bool, depth: usize) -> PathBuf {
+    let rel = if is_dir { path } else { path.parent().unwrap_or(path) };
+    let rel = rel.strip_prefix(&APP.dir).unwrap_or(rel);
+    let mut group = APP.dir.to_path_buf();
+    group.extend(rel.components().take(depth));
+    group
+}
+
+// the directory at --top-per-dir-depth an entry falls under - none for
+// that directory itself and anything above it
+fn top_group(path: &Path, is_dir: bool) -> Option<PathBuf> {
+    let group = group_at_depth(path, is_dir, APP.top_per_dir_depth);
+    let depth = group.strip_prefix(&APP.dir).map(|r| r.components().count()).unwrap_or(0);
+    if depth == APP.top_per_dir_depth && group != path {
+        Some(group)
+    } else {
+        None
+    }
+}
+
+// names already looked up - listings ask again for every entry
+#[cfg(target_family = "unix")]
+thread_local! {
+    static USER_NAMES: RefCell<HashMap<u32, String>> = RefCell::new(HashMap::new());
+    static GROUP_NAMES: RefCell<HashMap<u32, String>> = RefCell::new(HashMap::new());
+}
+
+#[cfg(target_family = "unix")]
+fn user_name(uid: u32) -> String {
+    USER_NAMES.with(|m| m.borrow_mut().entry(uid).or_insert_with(|| match get_user_by_uid(uid) {
+        None => format!("uid{}", uid),
+        Some(user) => user.name().to_string_lossy().to_string(),
+    }).clone())
+}
+
+#[cfg(target_family = "windows")]
+fn user_name(uid: u32) -> String {
+    format!("uid{}", uid)
+}
+
+#[cfg(target_family = "unix")]
+fn group_name(gid: u32) -> String {
+    GROUP_NAMES.with(|m| m.borrow_mut().entry(gid).or_insert_with(|| match get_group_by_gid(gid) {
+        None => format!("gid{}", gid),
+        Some(group) => group.name().to_string_lossy().to_string(),
+    }).clone())
+}
+
+#[cfg(target_family = "windows")]
+fn group_name(gid: u32) -> String {
+    format!("gid{}", gid)
+}
+
+// space actually allocated on disk for the file
+#[cfg(target_family = "unix")]
+fn allocated(md: &Metadata) -> u64 {
+    // st_blocks is always in 512 byte units regardless of the fs block size
+    md.blocks() * 512
+}
+
+#[cfg(target_family = "windows")]
+fn allocated(md: &Metadata) -> u64 {
+    md.len()
+}
+
 //noinspection ALL
-fn perk_up_disk_usage(top: &mut AllStats, list: &Vec<(PathBuf, Metadata)>) -> Result<()> {
-    if list.len() > 0 {
-        if let Some(mut parent) = list[0].0.ancestors().skip(1).next() {
-            if child_is_higher_than_base_dir(&APP.dir, &parent)? {
+fn perk_up_disk_usage(top: &mut AllStats, list: &MetaList) -> Result<()> {
+    if !list.is_empty() {
+        if let Some(mut parent) = list[0].0.ancestors().nth(1) {
+            if child_is_higher_than_base_dir(&APP.dir, parent)? {
                 return Ok(())
             }
 
             let dstats = {
-                let mut dstats: &mut DirStats = if top.dtree.contains_key(parent) {
+                let dstats: &mut DirStats = if top.dtree.contains_key(parent) {
                     top.dtree.get_mut(parent).unwrap()
                 } else {
                     let dstats = DirStats::new();
@@ -431,69 +656,237 @@ fn perk_up_disk_usage(top: &mut AllStats, list: &Vec<(PathBuf, Metadata)>) -> Re
                 };
                 for afile in list {
                     let filetype = afile.1.file_type();
+                    if filetype.is_symlink() {
+                        // only here when checking links and they do not count as usage
+                        if let Some(state) = afile.2 {
+                            *top.link_states.entry(state).or_insert(0) += 1;
+                            if state != links::OK {
+                                let c = top.bad_link_dirs.entry(parent.to_path_buf()).or_insert((0, 0, 0));
+                                match state {
+                                    links::BROKEN => c.0 += 1,
+                                    links::OUTSIDE => c.1 += 1,
+                                    _ => c.2 += 1,
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    if APP.audit {
+                        top.audit.add(&afile.0, &afile.1);
+                    }
+                    if !filetype.is_file() && !filetype.is_dir() {
+                        continue;
+                    }
+                    if APP.orphans {
+                        top.orphans.add(&afile.0, &afile.1);
+                    }
                     let f_age = afile.1.modified()?;
 
                     track_top_n(&mut top.top_files, &afile.0.to_path_buf(), afile.1.len(), APP.limit);
+                    if APP.top_per_dir && filetype.is_file() {
+                        if let Some(group) = top_group(&afile.0, false) {
+                            track_top_n(&mut top.group_tops.entry(group).or_default().1, &afile.0, afile.1.len(), APP.limit);
+                        }
+                    }
 
                     #[cfg(target_family = "windows")]
                         let uid = 0;
                     #[cfg(target_family = "unix")]
                         let uid = afile.1.uid();
-                    let ref mut tt = *top.user_map.entry(uid).or_insert((0, 0));
+                    let tt = &mut *top.user_map.entry(uid).or_insert((0, 0));
                     tt.0 += 1;
                     tt.1 += afile.1.len();
                     top.total_usage += afile.1.len();
 
+                    if APP.prom_file.is_some() || APP.quotas.is_some() {
+                        #[cfg(target_family = "windows")]
+                            let gid = 0;
+                        #[cfg(target_family = "unix")]
+                            let gid = afile.1.gid();
+                        let tt = top.group_map.entry(gid).or_insert((0, 0));
+                        tt.0 += 1;
+                        tt.1 += afile.1.len();
+                        if APP.quotas.is_some() && filetype.is_file() {
+                            *top.user_files.entry(uid).or_default() += 1;
+                            *top.group_files.entry(gid).or_default() += 1;
+                        }
+                    }
+
+                    if APP.user_dir_matrix {
+                        let group = group_at_depth(&afile.0, filetype.is_dir(), APP.matrix_depth);
+                        let tt = top.user_dir_map.entry(group).or_default().entry(uid).or_insert((0, 0));
+                        tt.0 += 1;
+                        tt.1 += afile.1.len();
+                    }
+
                     if filetype.is_file() {
-                        if APP.file_newer_than.map_or(true, |x| x < f_age) && APP.file_older_than.map_or(true, |x| x > f_age) {
-                            if let Some(ext) = multi_extension(&afile.0) {
+                        if APP.file_newer_than.is_none_or(|x| x < f_age) && APP.file_older_than.is_none_or(|x| x > f_age) {
+                            if let Some(ext) = ext_key(&afile.0) {
                                 match top.extensions.get_mut(ext.as_ref()) {
-                                    Some(ext_sz) => *ext_sz += afile.1.len(),
-                                    None => { top.extensions.insert(ext.to_string(), afile.1.len()); }
+                                    Some(ext_st) => ext_st.add(afile.1.len()),
+                                    None => { top.extensions.entry(ext.to_string()).or_default().add(afile.1.len()); }
+                                }
+                                if APP.ext_per_user {
+                                    top.user_ext_map.entry(uid).or_default().entry(ext.to_string()).or_default().add(afile.1.len());
                                 }
                             };
+                            if let Some(cl) = &APP.classifier {
+                                let cat = cl.classify(&afile.0).unwrap_or("other");
+                                match top.categories.get_mut(cat) {
+                                    Some(cat_st) => cat_st.add(afile.1.len()),
+                                    None => top.categories.entry(cat.to_string()).or_default().add(afile.1.len()),
+                                }
+                            }
+                            if let Some(kind) = afile.2 {
+                                top.content_types.entry(kind).or_default().add(afile.1.len());
+                            }
+                            if APP.dupes {
+                                #[cfg(target_family = "unix")]
+                                    let id = Some((afile.1.dev(), afile.1.ino()));
+                                #[cfg(target_family = "windows")]
+                                    let id = None;
+                                top.size_groups.add(&afile.0, afile.1.len(), id);
+                            }
 
+                            if APP.sparse_slack {
+                                let alloc = allocated(&afile.1);
+                                if alloc < afile.1.len() {
+                                    if afile.1.len() - alloc >= APP.sparse_min_gap {
+                                        track_top_n(&mut top.top_sparse, &afile.0, afile.1.len() - alloc, APP.limit);
+                                    }
+                                } else {
+                                    dstats.slack_directly += alloc - afile.1.len();
+                                    top.total_slack += alloc - afile.1.len();
+                                }
+                            }
+
+                            if afile.1.len() == 0 && (APP.empty || APP.list_empty) {
+                                top.empty_per_dir.entry(group_at_depth(&afile.0, false, 1)).or_default().0 += 1;
+                                if APP.list_empty {
+                                    top.empty_files.push((afile.0.clone(), afile.1.clone()));
+                                }
+                            }
+
+                            let age = top.started.duration_since(f_age).unwrap_or_default();
+                            dstats.age_range.update_direct(&age);
+                            dstats.age_range.update_recursive(&age);
                             dstats.file_count_directly += 1;
                             dstats.file_count_recursively += 1;
                             dstats.size_directly += afile.1.len();
                             dstats.size_recursively += afile.1.len();
                         }
-                    } else if filetype.is_dir() {
-                        if APP.file_newer_than.map_or(true, |x| x < f_age) && APP.file_older_than.map_or(true, |x| x > f_age) {
+                    } else if filetype.is_dir()
+                        && APP.file_newer_than.is_none_or(|x| x < f_age) && APP.file_older_than.is_none_or(|x| x > f_age) {
                             dstats.dir_count_directly += 1;
                             dstats.dir_count_recursively += 1;
                             // eprintln!("dir size {} :: {}", afile.0.display(), afile.1.len());
                             dstats.size_directly += afile.1.len();
                             dstats.size_recursively += afile.1.len();
                         }
-                    }
                 }
                 dstats.clone()
             };
+            // a directory with nothing in it never gets a list of its own so
+            // put it in the tree here, as every scanned directory is reported
+            if APP.usage_mode || APP.list_empty {
+                for afile in list.iter().filter(|x| x.1.is_dir()) {
+                    if !top.dtree.contains_key(&afile.0) {
+                        top.dtree.insert(afile.0.clone(), DirStats::new());
+                    }
+                }
+            }
             // go up tree and add stuff
-            loop {
-                if let Some(nextpar) = parent.ancestors().skip(1).next() {
-                    if parent == APP.dir { break; }
+            while let Some(nextpar) = parent.ancestors().nth(1) {
+                if parent == APP.dir { break; }
 
-                    if nextpar == parent {
-                        break;
-                    }
-                    let mut upstats = if top.dtree.contains_key(nextpar) {
-                        top.dtree.get_mut(nextpar).unwrap()
-                    } else {
-                        let dstats = DirStats::new();
-                        top.dtree.insert(nextpar.to_path_buf(), dstats);
-                        top.dtree.get_mut(nextpar).unwrap()
-                    };
-                    upstats.size_recursively += dstats.size_recursively;
-                    upstats.file_count_recursively += dstats.file_count_recursively;
-                    upstats.dir_count_recursively += dstats.dir_count_recursively;
-
-                    //eprintln!("up: {} from {}", nextpar.display(), parent.display());
-                    parent = nextpar;
-                } else {
+                if nextpar == parent {
                     break;
                 }
+                let upstats = if top.dtree.contains_key(nextpar) {
+                    top.dtree.get_mut(nextpar).unwrap()
+                } else {
+                    let dstats = DirStats::new();
+                    top.dtree.insert(nextpar.to_path_buf(), dstats);
+                    top.dtree.get_mut(nextpar).unwrap()
+                };
+                upstats.size_recursively += dstats.size_recursively;
+                upstats.file_count_recursively += dstats.file_count_recursively;
+                upstats.dir_count_recursively += dstats.dir_count_recursively;
+                for age in [dstats.age_range.oldest_file_recursive, dstats.age_range.newest_file_recursive].iter().flatten() {
+                    upstats.age_range.update_recursive(age);
+                }
+
+                //eprintln!("up: {} from {}", nextpar.display(), parent.display());
+                parent = nextpar;
+            }
+        }
+    }
+    Ok(())
+}
+
+// keep only the top most directories with no files anywhere beneath them
+fn find_empty_dirs(stats: &mut AllStats) {
+    let mut found = vec![];
+    for (p, ds) in stats.dtree.iter() {
+        if ds.file_count_recursively > 0 {
+            continue;
+        }
+        let parent_empty = p.parent()
+            .filter(|_| p != &APP.dir)
+            .and_then(|par| stats.dtree.get(par))
+            .is_some_and(|par| par.file_count_recursively == 0);
+        if !parent_empty {
+            push_raw_empty(p, &mut found);
+        }
+    }
+    for p in found {
+        stats.empty_per_dir.entry(group_at_depth(&p, true, 1)).or_default().1 += 1;
+        stats.empty_dirs.push(p);
+    }
+}
+
+// the scan does not count links, devices, filtered or aged out files so
+// look again at what is really in a directory that seems empty
+fn push_raw_empty(p: &Path, found: &mut Vec<PathBuf>) {
+    if raw_empty(p) {
+        found.push(p.to_path_buf());
+    } else if let Ok(rd) = std::fs::read_dir(p) {
+        for e in rd.flatten() {
+            if e.file_type().is_ok_and(|t| t.is_dir()) {
+                push_raw_empty(&e.path(), found);
+            }
+        }
+    }
+}
+
+// nothing but directories that are themselves empty all the way down
+fn raw_empty(p: &Path) -> bool {
+    match std::fs::read_dir(p) {
+        Err(_) => false,
+        Ok(mut rd) => rd.all(|e| match e {
+            Ok(e) => e.file_type().is_ok_and(|t| t.is_dir()) && raw_empty(&e.path()),
+            Err(_) => false,
+        }),
+    }
+}
+
+fn write_empty_list(out: &mut dyn Write, sorter: &mut Option<Sorter>, stats: &AllStats) -> Result<()> {
+    for (path, md) in stats.empty_files.iter() {
+        let out = entry(sorter, out, path)?;
+        if APP.print0 {
+            write!(out, "{}\0", show(path))?;
+        } else {
+            write_meta(out, path, md)?;
+        }
+    }
+    for path in stats.empty_dirs.iter() {
+        let out = entry(sorter, out, path)?;
+        if APP.print0 {
+            write!(out, "{}\0", show(path))?;
+        } else {
+            match symlink_metadata(path) {
+                Ok(md) => write_meta(out, path, &md)?,
+                Err(e) => eprintln!("{}: stat of empty dir: '{}', error: {}", *EXE, path.display(), e),
             }
         }
     }
@@ -504,10 +897,10 @@ fn perk_up_disk_usage(top: &mut AllStats, list: &Vec<(PathBuf, Metadata)>) -> Re
 fn file_track(startout: Instant,
               cputime: ProcessTime,
               stats: &mut AllStats,
-              out_q: &mut WorkerQueue<Option<Vec<(PathBuf, Metadata)>>>,
+              out_q: &mut WorkerQueue<Option<MetaList>>,
               work_q: &mut WorkerQueue<Option<PathBuf>>,
               t_status: &mut ThreadStatus,
-) -> Result<()> {
+) -> Result<i32> {
     t_status.register("started");
     let t_cpu_thread_time = cpu_time::ThreadTime::now();
     let count = Arc::new(AtomicUsize::new(0));
@@ -530,7 +923,7 @@ fn file_track(startout: Instant,
                 let stats_workers: QueueStats = sub_work_q.get_stats();
                 let stats_io: QueueStats = sub_out_q.get_stats();
                 eprint!("\rfiles: {}  rate: {}  blocked: {}  directory q len: {}  io q len: {}                 ",
-                        thiscount, rate, stats_workers.curr_poppers, stats_workers.curr_q_len, stats_io.curr_q_len);
+                        fmt_count(thiscount), fmt_count(rate), stats_workers.curr_poppers, fmt_count(stats_workers.curr_q_len), fmt_count(stats_io.curr_q_len));
                 if thiscount < last {
                     break;
                 }
@@ -540,8 +933,24 @@ fn file_track(startout: Instant,
     }
 
     let mut pop_count = 0;
-    if APP.list_files {
-        write_meta_header();
+    let mut list_out = ListOut::new(APP.list_output.as_deref())?;
+    let mut parquet = match &APP.list_parquet {
+        Some(path) => Some(ParquetList::new(path)?),
+        None => None,
+    };
+    let mut sorter = if APP.sorted {
+        Some(Sorter::new(APP.sort_memory as usize, APP.sort_dir.clone().unwrap_or_else(std::env::temp_dir)))
+    } else {
+        None
+    };
+    let mut sqlite = match &APP.sqlite {
+        Some(path) => Some(SqliteOut::new(path)?),
+        None => None,
+    };
+    if APP.list_links {
+        links::write_link_header(&mut list_out)?;
+    } else if APP.list_files && !APP.print0 && parquet.is_none() && APP.printf.is_none() {
+        write_meta_header(&mut list_out)?;
     }
 
     loop {
@@ -557,20 +966,41 @@ fn file_track(startout: Instant,
                 }
 
                 count.fetch_add(list.len(), Ordering::Relaxed);
-                if APP.usage_mode {
+                if APP.usage_mode || APP.list_empty {
                     if APP.update_status {
                         t_status.set_state("recording stats");
                     }
                     perk_up_disk_usage(stats, &list)?;
+                } else if APP.audit {
+                    // listing only but the exit status still tells of violations
+                    for (path, md, _) in list.iter().filter(|x| !x.1.file_type().is_symlink()) {
+                        stats.audit.add(path, md);
+                    }
                 }
-                if APP.list_files {
-                    for (path, md) in list {
+                if let Some(db) = &mut sqlite {
+                    db.add_files(&list)?;
+                }
+                if APP.list_files && !APP.list_empty {
+                    for (path, md, note) in list {
+                        if md.file_type().is_symlink() {
+                            // without --list-links link states only go to the summary
+                            if let Some(state) = note.filter(|s| *s != links::OK && APP.list_links) {
+                                links::write_link(entry(&mut sorter, &mut list_out, &path)?, &path, state)?;
+                            }
+                            continue;
+                        } else if APP.list_links || !(md.is_file() || md.is_dir())
+                            || (APP.list_orphans && !stats.orphans.is_orphaned(&md)) {
+                            continue;
+                        }
                         let f_age = md.modified()?;
-                        if APP.file_newer_than.map_or(true, |x| x < f_age) && APP.file_older_than.map_or(true, |x| x > f_age) {
+                        if APP.file_newer_than.is_none_or(|x| x < f_age) && APP.file_older_than.is_none_or(|x| x > f_age) {
                             if APP.t_status_interval {
                                 t_status.set_state("writing meta data");
                             }
-                            write_meta(&path, &md)?
+                            match &mut parquet {
+                                Some(pq) => pq.add(&path, &md)?,
+                                None => write_meta(entry(&mut sorter, &mut list_out, &path)?, &path, &md)?,
+                            }
                         }
                     }
                 }
@@ -584,28 +1014,51 @@ fn file_track(startout: Instant,
     let last_count = count.load(Ordering::Relaxed);
     count.store(0, Ordering::Relaxed);
 
+    if APP.empty || APP.list_empty {
+        find_empty_dirs(stats);
+    }
+    if APP.list_empty {
+        write_empty_list(&mut list_out, &mut sorter, stats)?;
+    }
+    if let Some(s) = sorter {
+        if APP.update_status {
+            t_status.set_state("merging sorted listing");
+        }
+        s.finish(&mut list_out)?;
+    }
+    list_out.finish()?;
+    if let Some(pq) = parquet {
+        pq.finish()?;
+    }
+
     if APP.usage_mode {
         let track_cpu_time = cpu_time::ThreadTime::now();
-        use num_format::{Locale, ToFormattedString};
-        println!("Scanned {} files / {} usage in [{:.3} / {:.3}] (real / cpu) seconds",
-                 last_count.to_formatted_string(&Locale::en),
-                 greek(stats.total_usage as f64),
-                 (Instant::now() - startout).as_secs_f64(), cputime.elapsed().as_secs_f64());
-        for x in stats.dtree.iter() {
-            track_top_n(&mut stats.top_dir, &x.0, x.1.size_directly, APP.limit); // track single immediate space
-            track_top_n(&mut stats.top_cnt_dir, &x.0, x.1.dir_count_directly, APP.limit); // track dir with most # of dir right under it
-            track_top_n(&mut stats.top_cnt_file, &x.0, x.1.file_count_directly, APP.limit); // track dir with most # of file right under it
-            track_top_n(&mut stats.top_cnt_overall, &x.0, x.1.file_count_recursively, APP.limit); // track overall count
-            track_top_n(&mut stats.top_dir_overall, &x.0, x.1.size_recursively, APP.limit); // track overall size
+        let (scan_real, scan_cpu) = (Instant::now() - startout, cputime.elapsed());
+        stats.scanned = (last_count, scan_real, scan_cpu);
+        if let Some(path) = &APP.prom_file {
+            prom::write_prom(path, stats, scan_real, scan_cpu)?;
+        }
+        track_dir_tops(stats);
+        if let Some(db) = sqlite {
+            if APP.update_status {
+                t_status.set_state("writing database");
+            }
+            db.finish(stats)?;
         }
 
         for x in stats.extensions.iter() {
-            track_top_n_ext(&mut stats.top_ext, &x.0, *x.1, APP.limit);
+            track_top_n_ext(&mut stats.top_ext, x.0, x.1.size, APP.limit);
         }
-        if APP.update_status {
-            t_status.set_state("print");
+        if APP.dupes {
+            if APP.update_status {
+                t_status.set_state("hashing duplicate candidates");
+            }
+            stats.dupes = find_dupes(&stats.size_groups)?;
+            stats.size_groups = SizeGroups::default();
+            if let Some(path) = &APP.dupes_list {
+                write_dupes_list(path, &stats.dupes)?;
+            }
         }
-        print_disk_report(&stats);
         eprintln!("perk cpu time: {}", track_cpu_time.elapsed().as_secs_f32());
     }
     if APP.update_status {
@@ -614,7 +1067,44 @@ fn file_track(startout: Instant,
     if APP.write_thread_cpu_time {
         eprintln!("file track thread cpu time: {:.3}", t_cpu_thread_time.elapsed().as_secs_f64());
     }
-    Ok(())
+    let mut exit_code = 0;
+    if APP.audit && stats.audit.violations() > 0 {
+        exit_code = audit::AUDIT_EXIT;
+    }
+    Ok(exit_code)
+}
+
+// (re)build the top N directory lists from the tree
+fn track_dir_tops(stats: &mut AllStats) {
+    stats.top_dir.clear();
+    stats.top_cnt_dir.clear();
+    stats.top_cnt_file.clear();
+    stats.top_cnt_overall.clear();
+    stats.top_dir_overall.clear();
+    stats.top_slack_dir.clear();
+    for x in stats.dtree.iter() {
+        track_top_n(&mut stats.top_dir, x.0, x.1.size_directly, APP.limit); // track single immediate space
+        track_top_n(&mut stats.top_cnt_dir, x.0, x.1.dir_count_directly, APP.limit); // track dir with most # of dir right under it
+        track_top_n(&mut stats.top_cnt_file, x.0, x.1.file_count_directly, APP.limit); // track dir with most # of file right under it
+        track_top_n(&mut stats.top_cnt_overall, x.0, x.1.file_count_recursively, APP.limit); // track overall count
+        track_top_n(&mut stats.top_dir_overall, x.0, x.1.size_recursively, APP.limit); // track overall size
+        if APP.sparse_slack && x.1.slack_directly > 0 {
+            track_top_n(&mut stats.top_slack_dir, x.0, x.1.slack_directly, APP.limit); // track block waste of files right under it
+        }
+    }
+    if APP.top_per_dir {
+        for t in stats.group_tops.values_mut() {
+            t.0.clear();
+        }
+        for (p, ds) in stats.dtree.iter() {
+            if let Some(group) = top_group(p, true) {
+                track_top_n(&mut stats.group_tops.entry(group).or_default().0, p, ds.size_recursively, APP.limit);
+            } else if p.strip_prefix(&APP.dir).is_ok_and(|r| r.components().count() == APP.top_per_dir_depth) {
+                // listed even with nothing under it
+                stats.group_tops.entry(p.clone()).or_default();
+            }
+        }
+    }
 }
 
 //noinspection ALL
@@ -644,7 +1134,7 @@ fn to_sort_vec_file_ext(heap: &BinaryHeap<TrackedExtension>) -> Vec<TrackedExten
 }
 
 //noinspection ALL
-fn print_disk_report(stats: &AllStats) {
+fn print_disk_report(out: &mut dyn Write, stats: &AllStats) -> Result<()> {
     #[derive(Debug)]
     struct U2u {
         count: u64,
@@ -656,88 +1146,271 @@ fn print_disk_report(stats: &AllStats) {
     user_vec.sort_by(|b, a| a.size.cmp(&b.size).then(b.uid.cmp(&b.uid)));
     //println!("File space scanned: {} and {} files in {} seconds", greek(total as f64), count, sec);
     if !user_vec.is_empty() {
-        println!("\nSpace/file-count per user");
+        writeln!(out, "\nSpace/file-count per user")?;
         for ue in &user_vec {
             #[cfg(target_family = "unix")]
             match get_user_by_uid(ue.uid) {
-                None => println!("uid{:7} {} / {}", ue.uid, greek(ue.size as f64), ue.count),
-                Some(user) => println!("{:10} {} / {}", user.name().to_string_lossy(), greek(ue.size as f64), ue.count),
+                None => writeln!(out, "uid{:7} {} / {}", ue.uid, fmt_size(ue.size), fmt_count(ue.count))?,
+                Some(user) => writeln!(out, "{:10} {} / {}", user.name().to_string_lossy(), fmt_size(ue.size), fmt_count(ue.count))?,
             }
             #[cfg(target_family = "windows")]
-            println!("uid{:>7} {} / {}", ue.uid, greek(ue.size as f64), ue.count);
+            writeln!(out, "uid{:>7} {} / {}", ue.uid, fmt_size(ue.size), fmt_count(ue.count))?;
+        }
+    }
+    if !stats.user_dir_map.is_empty() {
+        print_user_dir_matrix(out, stats)?;
+    }
+    if APP.empty {
+        writeln!(out, "\nEmpty files and top most empty directory trees per top level directory: {}", stats.empty_per_dir.len())?;
+        let mut tot = (0u64, 0u64);
+        for (p, c) in stats.empty_per_dir.iter() {
+            writeln!(out, "{:>14} files {:>10} dirs  {}", fmt_count(c.0), fmt_count(c.1), show(p))?;
+            tot.0 += c.0;
+            tot.1 += c.1;
         }
+        writeln!(out, "{:>14} files {:>10} dirs  total", fmt_count(tot.0), fmt_count(tot.1))?;
+    }
+    if APP.check_links {
+        let n = |s: &str| fmt_count(stats.link_states.get(s).copied().unwrap_or(0));
+        writeln!(out, "\nSymbolic links: {} ok  {} broken  {} outside root  {} other filesystem",
+                      n(links::OK), n(links::BROKEN), n(links::OUTSIDE), n(links::OTHER_FS))?;
+        let mut dirs: Vec<(&PathBuf, &(u64, u64, u64))> = stats.bad_link_dirs.iter().collect();
+        dirs.sort_by(|a, b| ((b.1).0 + (b.1).1 + (b.1).2).cmp(&((a.1).0 + (a.1).1 + (a.1).2)).then(a.0.cmp(b.0)));
+        if !dirs.is_empty() {
+            writeln!(out, "\nTop dir with broken / outside / other filesystem links: {}", std::cmp::min(APP.limit, dirs.len()))?;
+            for (p, c) in dirs.iter().take(APP.limit) {
+                writeln!(out, "{:>6} / {:>6} / {:>6} {}", fmt_count(c.0), fmt_count(c.1), fmt_count(c.2), show(p))?;
+            }
+        }
+    }
+    if APP.audit {
+        stats.audit.print(out)?;
+    }
+    if APP.orphans {
+        stats.orphans.print(out)?;
     }
     if !stats.top_dir.is_empty() {
-        println!("\nTop dir with space usage directly inside them: {}", stats.top_dir.len());
+        writeln!(out, "\nTop dir with space usage directly inside them: {}", stats.top_dir.len())?;
         for v in to_sort_vec(&stats.top_dir) {
-            println!("{:>14} {}", greek(v.size as f64), &v.path.display());
+            writeln!(out, "{:>14} {}", fmt_size(v.size), show(&v.path))?;
         }
     }
 
     if !stats.top_dir_overall.is_empty() {
-        println!("\nTop dir size recursive: {}", stats.top_dir_overall.len());
+        writeln!(out, "\nTop dir size recursive: {}", stats.top_dir_overall.len())?;
         for v in to_sort_vec(&stats.top_dir_overall) {
             //let rel = v.path.as_path().strip_prefix(CLI.dir.as_path()).unwrap();
-            println!("{:>14} {}", greek(v.size as f64), &v.path.display());
+            writeln!(out, "{:>14} {}", fmt_size(v.size), show(&v.path))?;
         }
     }
-    use num_format::{Locale, ToFormattedString};
 
     if !stats.top_cnt_overall.is_empty() {
-        println!("\nTop count of files recursive: {}", stats.top_cnt_overall.len());
+        writeln!(out, "\nTop count of files recursive: {}", stats.top_cnt_overall.len())?;
         for v in to_sort_vec(&stats.top_cnt_overall) {
-            println!("{:>14} {}", v.size.to_formatted_string(&Locale::en), &v.path.display());
+            writeln!(out, "{:>14} {}", fmt_count(v.size), show(&v.path))?;
         }
     }
 
     if !stats.top_cnt_file.is_empty() {
-        println!("\nTop counts of files in a single directory: {}", stats.top_cnt_file.len());
+        writeln!(out, "\nTop counts of files in a single directory: {}", stats.top_cnt_file.len())?;
         for v in to_sort_vec(&stats.top_cnt_file) {
-            println!("{:>14} {}", v.size.to_formatted_string(&Locale::en), &v.path.display());
+            writeln!(out, "{:>14} {}", fmt_count(v.size), show(&v.path))?;
         }
     }
 
     if !stats.top_cnt_dir.is_empty() {
-        println!("\nTop counts of directories in a single directory: {}", stats.top_cnt_dir.len());
+        writeln!(out, "\nTop counts of directories in a single directory: {}", stats.top_cnt_dir.len())?;
         for v in to_sort_vec(&stats.top_cnt_dir) {
-            println!("{:>14} {}", v.size.to_formatted_string(&Locale::en), &v.path.display());
+            writeln!(out, "{:>14} {}", fmt_count(v.size), show(&v.path))?;
         }
     }
     if !stats.top_files.is_empty() {
-        println!("\nTop largest file(s): {}", stats.top_files.len());
+        writeln!(out, "\nTop largest file(s): {}", stats.top_files.len())?;
         for v in to_sort_vec(&stats.top_files) {
-            println!("{:>14} {}", greek(v.size as f64), &v.path.display());
+            writeln!(out, "{:>14} {}", fmt_size(v.size), show(&v.path))?;
+        }
+    }
+    if !stats.group_tops.is_empty() {
+        let size = |g: &PathBuf| stats.dtree.get(g).map(|d| d.size_recursively).unwrap_or(0);
+        let mut groups: Vec<&PathBuf> = stats.group_tops.keys().collect();
+        groups.sort_by(|a, b| size(b).cmp(&size(a)).then(a.cmp(b)));
+        writeln!(out, "\nTop dirs and files under each directory at depth {}: {}", APP.top_per_dir_depth, groups.len())?;
+        for g in groups {
+            let (dirs, files) = &stats.group_tops[g];
+            writeln!(out, "\n{:>14} {}", fmt_size(size(g)), show(g))?;
+            if !dirs.is_empty() {
+                writeln!(out, "  Top dir size recursive: {}", dirs.len())?;
+                for v in to_sort_vec(dirs) {
+                    writeln!(out, "{:>14} {}", fmt_size(v.size), show(&v.path))?;
+                }
+            }
+            if !files.is_empty() {
+                writeln!(out, "  Top largest file(s): {}", files.len())?;
+                for v in to_sort_vec(files) {
+                    writeln!(out, "{:>14} {}", fmt_size(v.size), show(&v.path))?;
+                }
+            }
+        }
+    }
+    if !stats.top_sparse.is_empty() {
+        writeln!(out, "\nTop sparse file(s) by unallocated space: {}", stats.top_sparse.len())?;
+        for v in to_sort_vec(&stats.top_sparse) {
+            // only the top few so re-stat to show apparent vs allocated
+            match symlink_metadata(&v.path) {
+                Ok(md) => writeln!(out, "{:>14} {} apparent / {} allocated  {}", fmt_size(v.size),
+                                        fmt_size(md.len()), fmt_size(allocated(&md)), show(&v.path))?,
+                Err(_) => writeln!(out, "{:>14} {}", fmt_size(v.size), show(&v.path))?,
+            }
+        }
+    }
+    if APP.sparse_slack && !stats.top_slack_dir.is_empty() {
+        writeln!(out, "\nTop dir with block slack space directly inside them: {}  total slack: {}",
+                      stats.top_slack_dir.len(), fmt_size(stats.total_slack))?;
+        for v in to_sort_vec(&stats.top_slack_dir) {
+            writeln!(out, "{:>14} {}", fmt_size(v.size), show(&v.path))?;
         }
     }
     if !stats.top_ext.is_empty() {
-        println!("\nTop usage by file extension: {}", stats.top_ext.len());
+        writeln!(out, "\nTop usage by file extension: {}", stats.top_ext.len())?;
         for v in to_sort_vec_file_ext(&stats.top_ext) {
-            println!("{:>14} {}", greek(v.size as f64), &v.extension);
+            print_ext_line(out, &v.extension, &stats.extensions[&v.extension])?;
+        }
+    }
+    if !stats.categories.is_empty() {
+        let mut cats: Vec<(&String, &ExtStats)> = stats.categories.iter().collect();
+        cats.sort_by(|a, b| b.1.size.cmp(&a.1.size).then(a.0.cmp(b.0)));
+        writeln!(out, "\nUsage by file category: {}", cats.len())?;
+        for (cat, st) in cats {
+            print_ext_line(out, cat, st)?;
+        }
+    }
+    if !stats.content_types.is_empty() {
+        let mut kinds: Vec<(&&str, &ExtStats)> = stats.content_types.iter().collect();
+        kinds.sort_by(|a, b| b.1.size.cmp(&a.1.size).then(a.0.cmp(b.0)));
+        writeln!(out, "\nUsage by detected content type: {}", kinds.len())?;
+        for (kind, st) in kinds {
+            print_ext_line(out, kind, st)?;
+        }
+    }
+    if !stats.dupes.is_empty() {
+        let wasted: u64 = stats.dupes.iter().map(|s| s.wasted()).sum();
+        writeln!(out, "\nTop duplicate file sets by reclaimable space: {} of {} sets wasting {}",
+                      std::cmp::min(APP.limit, stats.dupes.len()), stats.dupes.len(), fmt_size(wasted))?;
+        for s in stats.dupes.iter().take(APP.limit) {
+            writeln!(out, "{:>14} {} x {}", fmt_size(s.wasted()), fmt_count(s.paths.len()), fmt_size(s.size))?;
+            for p in s.paths.iter() {
+                writeln!(out, "{:>14} {}", "", show(p))?;
+            }
+        }
+    }
+    if !stats.user_ext_map.is_empty() {
+        writeln!(out, "\nTop usage by file extension per user: {}", stats.user_ext_map.len())?;
+        for (uid, exts) in stats.user_ext_map.iter() {
+            let mut heap = BinaryHeap::new();
+            for x in exts.iter() {
+                track_top_n_ext(&mut heap, x.0, x.1.size, APP.limit);
+            }
+            writeln!(out, "{}:", user_name(*uid))?;
+            for v in to_sort_vec_file_ext(&heap) {
+                print_ext_line(out, &v.extension, &exts[&v.extension])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// the usage report and any quota violations - returns the quota exit status
+fn write_report(out: &mut dyn Write, stats: &AllStats) -> Result<i32> {
+    print_disk_report(out, stats)?;
+    match &APP.quotas {
+        None => Ok(0),
+        Some(q) => {
+            let v = q.check(stats);
+            quota::print(out, &v)?;
+            Ok(quota::exit_code(&v))
         }
     }
 }
 
+fn write_scanned(out: &mut dyn Write, stats: &AllStats) -> Result<()> {
+    let (count, real, cpu) = stats.scanned;
+    writeln!(out, "Scanned {} files / {} usage in [{:.3} / {:.3}] (real / cpu) seconds",
+             fmt_count(count),
+             fmt_size(stats.total_usage),
+             real.as_secs_f64(), cpu.as_secs_f64())?;
+    Ok(())
+}
+
+fn print_ext_line(out: &mut dyn Write, ext: &str, st: &ExtStats) -> Result<()> {
+    writeln!(out, "{:>14} {:>12} files  avg: {}  max: {}  {}", fmt_size(st.size),
+                  fmt_count(st.count), fmt_size(st.average()), fmt_size(st.largest), ext)?;
+    Ok(())
+}
 
 //noinspection ALL
-fn parls() -> Result<()> {
-    if APP.verbose > 0 { eprintln!("CLI: {:#?}", *APP); }
-    let mut q: WorkerQueue<Option<PathBuf>> = WorkerQueue::new(APP.no_threads, 0);
-    let mut oq: WorkerQueue<Option<Vec<(PathBuf, Metadata)>>> = WorkerQueue::new(1, 0);
-
-    let mut allstats = AllStats {
-        dtree: BTreeMap::new(),
-        extensions: BTreeMap::new(),
-        top_files: BinaryHeap::new(),
-        top_dir: BinaryHeap::new(),
-        top_cnt_dir: BinaryHeap::new(),
-        top_cnt_file: BinaryHeap::new(),
-        top_cnt_overall: BinaryHeap::new(),
-        top_dir_overall: BinaryHeap::new(),
-        top_ext: BinaryHeap::new(),
-        user_map: BTreeMap::new(),
-        total_usage: 0u64,
-    };
+fn print_user_dir_matrix(out: &mut dyn Write, stats: &AllStats) -> Result<()> {
+
+    let cell = |c: &(u64, u64)| format!("{} / {}", fmt_size(c.1), fmt_count(c.0));
+
+    // totals per user (columns) and per directory (rows)
+    let mut user_tot: BTreeMap<u32, (u64, u64)> = BTreeMap::new();
+    let mut rows: Vec<(&PathBuf, (u64, u64))> = vec![];
+    for (dir, users) in stats.user_dir_map.iter() {
+        let mut row_tot = (0u64, 0u64);
+        for (uid, c) in users.iter() {
+            let tt = user_tot.entry(*uid).or_insert((0, 0));
+            tt.0 += c.0;
+            tt.1 += c.1;
+            row_tot.0 += c.0;
+            row_tot.1 += c.1;
+        }
+        rows.push((dir, row_tot));
+    }
+    rows.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(b.0)));
+    let mut users: Vec<(u32, (u64, u64))> = user_tot.into_iter().collect();
+    users.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(&b.0)));
+    let grand = users.iter().fold((0u64, 0u64), |acc, u| (acc.0 + (u.1).0, acc.1 + (u.1).1));
+
+    let names: Vec<String> = users.iter().map(|u| user_name(u.0)).collect();
+    let widths: Vec<usize> = users.iter().zip(names.iter())
+        .map(|(u, n)| max(n.len(), cell(&u.1).len()))
+        .collect();
+    let path_w = rows.iter().map(|r| show(r.0).len()).max().unwrap_or(0).max(5);
+    let tot_w = max(5, cell(&grand).len());
+
+    writeln!(out, "\nSpace/file-count per user and directory at depth {}: {} x {}", APP.matrix_depth, rows.len(), users.len())?;
+    let mut line = format!("{:<width$}", "", width = path_w);
+    for (n, w) in names.iter().zip(widths.iter()) {
+        line.push_str(&format!("  {:>width$}", n, width = w));
+    }
+    line.push_str(&format!("  {:>width$}", "total", width = tot_w));
+    writeln!(out, "{}", line)?;
+
+    for (dir, row_tot) in rows.iter() {
+        let mut line = format!("{:<width$}", show(dir), width = path_w);
+        let cells = &stats.user_dir_map[*dir];
+        for (u, w) in users.iter().zip(widths.iter()) {
+            match cells.get(&u.0) {
+                None => line.push_str(&format!("  {:>width$}", "-", width = w)),
+                Some(c) => line.push_str(&format!("  {:>width$}", cell(c), width = w)),
+            }
+        }
+        line.push_str(&format!("  {:>width$}", cell(row_tot), width = tot_w));
+        writeln!(out, "{}", line)?;
+    }
 
+    let mut line = format!("{:<width$}", "total", width = path_w);
+    for (u, w) in users.iter().zip(widths.iter()) {
+        line.push_str(&format!("  {:>width$}", cell(&u.1), width = w));
+    }
+    line.push_str(&format!("  {:>width$}", cell(&grand), width = tot_w));
+    writeln!(out, "{}", line)?;
+    Ok(())
+}
+
+//noinspection ALL
+fn parls() -> Result<i32> {
+    if APP.verbose > 0 { eprintln!("CLI: {:#?}", *APP); }
 
     let mut tt = ThreadTracker::new();
     let mut main_status = tt.setup_thread("main", "setup");
@@ -747,6 +1420,97 @@ fn parls() -> Result<()> {
         spawn_death_timeout_thread(die_dur, &mut tt);
     };
 
+    main_status.set_state("monitor started");
+    if APP.t_status_on_key || APP.t_status_interval {
+        let tt = tt.clone();
+        thread::spawn(move || {
+            if APP.t_status_on_key {
+                tt.monitor_on_enter();
+            } else if APP.t_status_interval {
+                tt.monitor(APP.ticker_interval);
+            }
+        });
+    }
+
+    if APP.daemon {
+        return daemon::run(&mut main_status);
+    }
+
+    let mut report = Output::new(APP.report_output.as_deref())?;
+    match (APP.list_files, APP.usage_mode) {
+        // keep stdout to just the paths when they are NUL terminated
+        (true, false) if APP.print0 => eprintln!("List empty files and directories under: {}", show(&APP.dir)),
+        (true, true) => writeln!(report, "List file stats and disk usage summary for: {}", show(&APP.dir))?,
+        (false, true) => writeln!(report, "Scanning disk usage summary for: {}", show(&APP.dir))?,
+        (true, false) => writeln!(report, "List file stats under: {}", show(&APP.dir))?,
+        _ => Err(anyhow!("Error - neither usage or list mode specified"))?,
+    }
+    // ahead of the listing when both go to stdout
+    report.flush()?;
+
+    let startcpu = ProcessTime::now();
+    let (stats, mut exit_code) = scan(&mut tt, &mut main_status)?;
+    if APP.usage_mode {
+        write_scanned(&mut report, &stats)?;
+        exit_code = max(exit_code, write_report(&mut report, &stats)?);
+    }
+
+    if APP.print0 {
+        eprintln!("last cpu time: {}", startcpu.elapsed().as_secs_f32());
+    } else {
+        writeln!(report, "last cpu time: {}", startcpu.elapsed().as_secs_f32())?;
+    }
+    report.finish()?;
+    if APP.watch {
+        return watch_forever(stats, &mut tt, &mut main_status);
+    }
+    Ok(exit_code)
+}
+
+// keep reporting on changes, going back to full scans when the events
+// cannot be trusted and to only rescans when out of inotify watches
+#[cfg(target_os = "linux")]
+fn watch_forever(stats: AllStats, tt: &mut ThreadTracker, main_status: &mut ThreadStatus) -> Result<i32> {
+    let mut stats = Arc::new(RwLock::new(stats));
+    let mut watching = true;
+    loop {
+        if watching {
+            main_status.set_state("watching for changes");
+            let end = watch::watch(&stats, &mut |s, n| {
+                let mut report = Output::new(APP.report_output.as_deref())?;
+                writeln!(report, "\nUpdated after re-reading {} directories", n)?;
+                write_report(&mut report, s)?;
+                report.finish()
+            })?;
+            if let watch::WatchEnd::LimitReached = end {
+                eprintln!("{}: falling back to a full scan every {:?}", *EXE, APP.rescan_interval);
+                watching = false;
+            }
+        }
+        if !watching {
+            main_status.set_state("waiting to rescan");
+            thread::sleep(APP.rescan_interval);
+        }
+        let (fresh, _) = scan(tt, main_status)?;
+        let mut report = Output::new(APP.report_output.as_deref())?;
+        write_scanned(&mut report, &fresh)?;
+        write_report(&mut report, &fresh)?;
+        report.finish()?;
+        stats = Arc::new(RwLock::new(fresh));
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_forever(_stats: AllStats, _tt: &mut ThreadTracker, _main_status: &mut ThreadStatus) -> Result<i32> {
+    Err(anyhow!("--watch uses inotify and is only supported on linux"))
+}
+
+// one full pass over the tree - returns the stats gathered and the exit status
+fn scan(tt: &mut ThreadTracker, main_status: &mut ThreadStatus) -> Result<(AllStats, i32)> {
+    let mut q: WorkerQueue<Option<PathBuf>> = WorkerQueue::new(APP.no_threads, 0);
+    let mut oq: WorkerQueue<Option<MetaList>> = WorkerQueue::new(1, 0);
+
+    let mut allstats = AllStats::new();
 
     q.push(Some(APP.dir.to_path_buf())).with_context(|| format!("Cannot push top path: {}", APP.dir.display()))?;
     let startout = Instant::now();
@@ -767,27 +1531,12 @@ fn parls() -> Result<()> {
         let mut c_q = q.clone();
         let mut ft_status = tt.setup_thread("file_trk", "starting...");
         //let mut c_ft_status = ft_status.clone();
-        spawn(move || file_track(startout, startcpu, &mut allstats, &mut c_oq, &mut c_q, &mut ft_status))
+        spawn(move || {
+            file_track(startout, startcpu, &mut allstats, &mut c_oq, &mut c_q, &mut ft_status)
+                .map(|code| (allstats, code))
+        })
     };
 
-    main_status.set_state("monitor started");
-    if APP.t_status_on_key || APP.t_status_interval {
-        thread::spawn(move || {
-            if APP.t_status_on_key {
-                tt.monitor_on_enter();
-            } else if APP.t_status_interval {
-                tt.monitor(APP.ticker_interval);
-            }
-        });
-    }
-
-
-    match (APP.list_files, APP.usage_mode) {
-        (true, true) => println!("List file stats and disk usage summary for: {}", APP.dir.display()),
-        (false, true) => println!("Scanning disk usage summary for: {}", APP.dir.display()),
-        (true, false) => println!("List file stats under: {}", APP.dir.display()),
-        _ => Err(anyhow!("Error - neither usage or list mode specified"))?,
-    }
     main_status.set_state("wait on queue finish");
 
     loop {
@@ -808,10 +1557,7 @@ fn parls() -> Result<()> {
     if APP.verbose > 0 { eprintln!("push none of out queue"); }
     oq.push(None)?;
     if APP.verbose > 0 { eprintln!("joining out thread"); }
-    w_h.join().expect("cannot join a output thread")?;
-
-
-    println!("last cpu time: {}", startcpu.elapsed().as_secs_f32());
-    Ok(())
+    let res = w_h.join().expect("cannot join a output thread")?;
+    main_status.set_state("scan done");
+    Ok(res)
 }
-
@@ -0,0 +1,103 @@
+use std::collections::{BinaryHeap, BTreeMap};
+use std::fs::Metadata;
+use std::io::Write;
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+#[cfg(target_family = "unix")]
+use users::{get_group_by_gid, get_user_by_uid};
+
+use anyhow::Result;
+
+use crate::cli::APP;
+use crate::util::{fmt_count, fmt_size, show};
+use crate::{to_sort_vec, track_top_n};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Owner {
+    User(u32),
+    Group(u32),
+}
+
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Owner::User(id) => write!(f, "uid {}", id),
+            Owner::Group(id) => write!(f, "gid {}", id),
+        }
+    }
+}
+
+/// Space owned by uids and gids that no longer resolve to an account
+#[derive(Default)]
+pub struct OrphanStats {
+    // account lookups are a libc call each time so remember the answer
+    known: BTreeMap<Owner, bool>,
+    usage: BTreeMap<Owner, (u64, u64)>,
+    dirs: BTreeMap<Owner, BTreeMap<PathBuf, u64>>,
+}
+
+impl OrphanStats {
+    #[cfg(target_family = "unix")]
+    fn is_orphan(&mut self, o: Owner) -> bool {
+        *self.known.entry(o).or_insert_with(|| match o {
+            Owner::User(id) => get_user_by_uid(id).is_none(),
+            Owner::Group(id) => get_group_by_gid(id).is_none(),
+        })
+    }
+
+    #[cfg(target_family = "windows")]
+    fn is_orphan(&mut self, _o: Owner) -> bool {
+        false
+    }
+
+    #[cfg(target_family = "unix")]
+    fn owners(md: &Metadata) -> [Owner; 2] {
+        [Owner::User(md.uid()), Owner::Group(md.gid())]
+    }
+
+    #[cfg(target_family = "windows")]
+    fn owners(_md: &Metadata) -> [Owner; 2] {
+        [Owner::User(0), Owner::Group(0)]
+    }
+
+    /// True when either the owning user or group of the entry has no account
+    pub fn is_orphaned(&mut self, md: &Metadata) -> bool {
+        let [u, g] = Self::owners(md);
+        self.is_orphan(u) || self.is_orphan(g)
+    }
+
+    pub fn add(&mut self, path: &Path, md: &Metadata) {
+        for o in Self::owners(md).iter() {
+            if self.is_orphan(*o) {
+                let tt = self.usage.entry(*o).or_insert((0, 0));
+                tt.0 += 1;
+                tt.1 += md.len();
+                if let Some(parent) = path.parent() {
+                    *self.dirs.entry(*o).or_default().entry(parent.to_path_buf()).or_insert(0) += md.len();
+                }
+            }
+        }
+    }
+
+    pub fn print(&self, out: &mut dyn Write) -> Result<()> {
+        let mut owners: Vec<(&Owner, &(u64, u64))> = self.usage.iter().collect();
+        owners.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(b.0)));
+        writeln!(out, "\nSpace/file-count owned by uids and gids with no account: {}", owners.len())?;
+        for (o, c) in owners.iter() {
+            writeln!(out, "{:>10} {} / {}", o.to_string(), fmt_size(c.1), fmt_count(c.0))?;
+        }
+        for (o, _) in owners.iter() {
+            let mut heap = BinaryHeap::new();
+            for (p, sz) in self.dirs[o].iter() {
+                track_top_n(&mut heap, p, *sz, APP.limit);
+            }
+            writeln!(out, "\nTop dir with space owned by {} directly inside them: {}", o, heap.len())?;
+            for v in to_sort_vec(&heap) {
+                writeln!(out, "{:>14} {}", fmt_size(v.size), show(&v.path))?;
+            }
+        }
+        Ok(())
+    }
+}
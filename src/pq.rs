@@ -0,0 +1,141 @@
+use std::fs::Metadata;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arrow_array::builder::{Int32Builder, Int64Builder, StringBuilder, TimestampMicrosecondBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use crate::util::{ext_key, micros, show, unix_fields, Output};
+
+// rows gathered before they are handed to the writer, and rows per row group
+const BATCH_ROWS: usize = 64 * 1024;
+const GROUP_ROWS: usize = 1024 * 1024;
+
+/// Writes listing entries as Parquet rows with typed columns
+///
+/// Entries collect in column builders and go to the writer a batch at a
+/// time, which cuts them into row groups.  Times are microseconds in UTC.
+pub struct ParquetList {
+    schema: SchemaRef,
+    w: ArrowWriter<Output>,
+    rows: usize,
+    path: StringBuilder,
+    parent: StringBuilder,
+    name: StringBuilder,
+    extension: StringBuilder,
+    size: Int64Builder,
+    blocks: Int64Builder,
+    uid: Int64Builder,
+    gid: Int64Builder,
+    mode: Int32Builder,
+    mtime: TimestampMicrosecondBuilder,
+    atime: TimestampMicrosecondBuilder,
+    ctime: TimestampMicrosecondBuilder,
+    inode: Int64Builder,
+    nlink: Int64Builder,
+}
+
+impl ParquetList {
+    pub fn new(path: &Path) -> Result<ParquetList> {
+        let ts = || DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()));
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("parent", DataType::Utf8, false),
+            Field::new("name", DataType::Utf8, false),
+            Field::new("extension", DataType::Utf8, true),
+            Field::new("size", DataType::Int64, false),
+            Field::new("blocks", DataType::Int64, false),
+            Field::new("uid", DataType::Int64, false),
+            Field::new("gid", DataType::Int64, false),
+            Field::new("mode", DataType::Int32, false),
+            Field::new("mtime", ts(), true),
+            Field::new("atime", ts(), true),
+            Field::new("ctime", ts(), true),
+            Field::new("inode", DataType::Int64, false),
+            Field::new("nlink", DataType::Int64, false),
+        ]));
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .set_max_row_group_row_count(Some(GROUP_ROWS))
+            .build();
+        let w = ArrowWriter::try_new(Output::new(Some(path))?, schema.clone(), Some(props))?;
+        let ts_builder = || TimestampMicrosecondBuilder::with_capacity(BATCH_ROWS).with_timezone("UTC");
+        Ok(ParquetList {
+            schema,
+            w,
+            rows: 0,
+            path: StringBuilder::new(),
+            parent: StringBuilder::new(),
+            name: StringBuilder::new(),
+            extension: StringBuilder::new(),
+            size: Int64Builder::with_capacity(BATCH_ROWS),
+            blocks: Int64Builder::with_capacity(BATCH_ROWS),
+            uid: Int64Builder::with_capacity(BATCH_ROWS),
+            gid: Int64Builder::with_capacity(BATCH_ROWS),
+            mode: Int32Builder::with_capacity(BATCH_ROWS),
+            mtime: ts_builder(),
+            atime: ts_builder(),
+            ctime: ts_builder(),
+            inode: Int64Builder::with_capacity(BATCH_ROWS),
+            nlink: Int64Builder::with_capacity(BATCH_ROWS),
+        })
+    }
+
+    pub fn add(&mut self, path: &Path, md: &Metadata) -> Result<()> {
+        let (blocks, uid, gid, mode, ctime, inode, nlink) = unix_fields(md);
+        self.path.append_value(show(path));
+        self.parent.append_value(path.parent().map(show).unwrap_or_default());
+        self.name.append_value(path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default());
+        self.extension.append_option(if md.is_file() { ext_key(path) } else { None });
+        self.size.append_value(md.len() as i64);
+        self.blocks.append_value(blocks);
+        self.uid.append_value(uid);
+        self.gid.append_value(gid);
+        self.mode.append_value(mode);
+        self.mtime.append_option(micros(md.modified()));
+        self.atime.append_option(micros(md.accessed()));
+        self.ctime.append_option(ctime);
+        self.inode.append_value(inode);
+        self.nlink.append_value(nlink);
+        self.rows += 1;
+        if self.rows >= BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let cols: Vec<ArrayRef> = vec![
+            Arc::new(self.path.finish()),
+            Arc::new(self.parent.finish()),
+            Arc::new(self.name.finish()),
+            Arc::new(self.extension.finish()),
+            Arc::new(self.size.finish()),
+            Arc::new(self.blocks.finish()),
+            Arc::new(self.uid.finish()),
+            Arc::new(self.gid.finish()),
+            Arc::new(self.mode.finish()),
+            Arc::new(self.mtime.finish()),
+            Arc::new(self.atime.finish()),
+            Arc::new(self.ctime.finish()),
+            Arc::new(self.inode.finish()),
+            Arc::new(self.nlink.finish()),
+        ];
+        self.w.write(&RecordBatch::try_new(self.schema.clone(), cols)?)?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        if self.rows > 0 {
+            self.flush()?;
+        }
+        // writes the footer on the way out
+        self.w.into_inner()?.finish()
+    }
+}
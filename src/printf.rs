@@ -0,0 +1,385 @@
+use std::borrow::Cow;
+use std::fs::Metadata;
+use std::io::Write;
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::cli::APP;
+use crate::util::{ext_key, micros, relative, show, unix_fields};
+use crate::{group_name, user_name};
+
+#[derive(Debug, Clone, Copy)]
+enum TimeOf {
+    Access,
+    Change,
+    Modify,
+    Birth,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Path,
+    RelPath,
+    Name,
+    Parent,
+    Ext,
+    Type,
+    Size,
+    Blocks,
+    Kb,
+    User,
+    Uid,
+    Group,
+    Gid,
+    Mode,
+    SymMode,
+    Inode,
+    Nlink,
+    Dev,
+    Depth,
+    // when and whether as epoch seconds or ISO 8601
+    Time(TimeOf, bool),
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Lit(String),
+    // field, width and whether it is left aligned
+    Field(Field, usize, bool),
+}
+
+/// A listing line template in the style of find -printf
+///
+/// Nothing is added to the template so it needs its own \n.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut pieces = vec![];
+        let mut lit = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => lit.push(match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('\\') => '\\',
+                    Some(x) => return Err(anyhow!("unknown escape \\{} in template", x)),
+                    None => return Err(anyhow!("template ends with a lone \\")),
+                }),
+                '%' => {
+                    if chars.peek() == Some(&'%') {
+                        chars.next();
+                        lit.push('%');
+                        continue;
+                    }
+                    let left = chars.peek() == Some(&'-');
+                    if left {
+                        chars.next();
+                    }
+                    let mut width = 0;
+                    while let Some(d) = chars.peek().and_then(|d| d.to_digit(10)) {
+                        width = width * 10 + d as usize;
+                        chars.next();
+                    }
+                    let f = match chars.next() {
+                        Some('p') => Field::Path,
+                        Some('P') => Field::RelPath,
+                        Some('f') => Field::Name,
+                        Some('h') => Field::Parent,
+                        Some('e') => Field::Ext,
+                        Some('y') => Field::Type,
+                        Some('s') => Field::Size,
+                        Some('b') => Field::Blocks,
+                        Some('k') => Field::Kb,
+                        Some('u') => Field::User,
+                        Some('U') => Field::Uid,
+                        Some('g') => Field::Group,
+                        Some('G') => Field::Gid,
+                        Some('m') => Field::Mode,
+                        Some('M') => Field::SymMode,
+                        Some('i') => Field::Inode,
+                        Some('n') => Field::Nlink,
+                        Some('D') => Field::Dev,
+                        Some('d') => Field::Depth,
+                        Some(t @ ('A' | 'B' | 'C' | 'T')) => {
+                            let of = match t {
+                                'A' => TimeOf::Access,
+                                'B' => TimeOf::Birth,
+                                'C' => TimeOf::Change,
+                                _ => TimeOf::Modify,
+                            };
+                            match chars.next() {
+                                Some('@') => Field::Time(of, false),
+                                Some('+') => Field::Time(of, true),
+                                _ => return Err(anyhow!("%{} must be followed by @ for epoch seconds or + for ISO 8601", t)),
+                            }
+                        }
+                        Some(x) => return Err(anyhow!("unknown directive %{} in template", x)),
+                        None => return Err(anyhow!("template ends with a lone %")),
+                    };
+                    if !lit.is_empty() {
+                        pieces.push(Piece::Lit(std::mem::take(&mut lit)));
+                    }
+                    pieces.push(Piece::Field(f, width, left));
+                }
+                _ => lit.push(c),
+            }
+        }
+        if !lit.is_empty() {
+            pieces.push(Piece::Lit(lit));
+        }
+        Ok(Template { pieces })
+    }
+}
+
+impl Template {
+    pub fn write(&self, out: &mut dyn Write, path: &Path, md: &Metadata) -> Result<()> {
+        let mut line = String::with_capacity(128);
+        for p in self.pieces.iter() {
+            match p {
+                Piece::Lit(s) => line.push_str(s),
+                Piece::Field(f, w, left) => {
+                    let v = value(*f, path, md);
+                    if *left {
+                        line.push_str(&format!("{:<w$}", v, w = w));
+                    } else {
+                        line.push_str(&format!("{:>w$}", v, w = w));
+                    }
+                }
+            }
+        }
+        out.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn value<'a>(f: Field, path: &'a Path, md: &Metadata) -> Cow<'a, str> {
+    let (blocks, uid, gid, mode, ctime, inode, nlink) = unix_fields(md);
+    match f {
+        Field::Path => show(path),
+        Field::RelPath => relative(path),
+        Field::Name => path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(),
+        Field::Parent => path.parent().map(show).unwrap_or(Cow::Borrowed(".")),
+        Field::Ext => if md.is_file() { ext_key(path).unwrap_or_default() } else { Cow::Borrowed("") },
+        Field::Type => Cow::Borrowed(type_char(md)),
+        Field::Size => md.len().to_string().into(),
+        Field::Blocks => blocks.to_string().into(),
+        Field::Kb => ((blocks + 1) / 2).to_string().into(),
+        Field::User => user_name(uid as u32).into(),
+        Field::Uid => uid.to_string().into(),
+        Field::Group => group_name(gid as u32).into(),
+        Field::Gid => gid.to_string().into(),
+        Field::Mode => format!("{:o}", mode & 0o7777).into(),
+        Field::SymMode => symbolic_mode(md, mode as u32).into(),
+        Field::Inode => inode.to_string().into(),
+        Field::Nlink => nlink.to_string().into(),
+        Field::Dev => dev(md).to_string().into(),
+        Field::Depth => path.strip_prefix(&APP.dir).map(|r| r.components().count()).unwrap_or(0).to_string().into(),
+        Field::Time(of, iso) => match secs(of, md, ctime) {
+            None => Cow::Borrowed("?"),
+            Some(s) if iso => iso_8601(s).into(),
+            Some(s) => s.to_string().into(),
+        },
+    }
+}
+
+// epoch seconds of one of the times - birth comes from statx on linux and
+// is missing where the filesystem does not record it
+fn secs(of: TimeOf, md: &Metadata, ctime: Option<i64>) -> Option<i64> {
+    let t = match of {
+        TimeOf::Access => micros(md.accessed()),
+        TimeOf::Birth => micros(md.created()),
+        TimeOf::Change => ctime,
+        TimeOf::Modify => micros(md.modified()),
+    };
+    t.map(|t| t.div_euclid(1_000_000))
+}
+
+/// Extra columns added to the end of each listing line
+#[derive(Debug, Clone)]
+pub struct Columns {
+    cols: Vec<(&'static str, Field)>,
+}
+
+const COLUMNS: [(&str, &str, Field); 9] = [
+    ("inode", "inode", Field::Inode),
+    ("dev", "device", Field::Dev),
+    ("nlink", "nlink", Field::Nlink),
+    ("gid", "gid", Field::Gid),
+    ("group", "group", Field::Group),
+    ("blocks", "blocks", Field::Blocks),
+    ("atime", "epoch_last_access", Field::Time(TimeOf::Access, false)),
+    ("ctime", "epoch_last_change", Field::Time(TimeOf::Change, false)),
+    ("btime", "epoch_birth", Field::Time(TimeOf::Birth, false)),
+];
+
+impl FromStr for Columns {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut cols = vec![];
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match COLUMNS.iter().find(|c| c.0 == name) {
+                Some(c) => cols.push((c.1, c.2)),
+                None => return Err(anyhow!("unknown column \"{}\", expected some of: {}", name,
+                                           COLUMNS.iter().map(|c| c.0).collect::<Vec<_>>().join(", "))),
+            }
+        }
+        Ok(Columns { cols })
+    }
+}
+
+impl Columns {
+    pub fn write_header(&self, out: &mut dyn Write) -> Result<()> {
+        for (name, _) in self.cols.iter() {
+            write!(out, "{}{}", APP.delimiter, name)?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, out: &mut dyn Write, path: &Path, md: &Metadata) -> Result<()> {
+        let ctime = unix_fields(md).4;
+        for (_, f) in self.cols.iter() {
+            match f {
+                // a time the filesystem does not have is left empty
+                Field::Time(of, _) => match secs(*of, md, ctime) {
+                    Some(s) => write!(out, "{}{}", APP.delimiter, s)?,
+                    None => write!(out, "{}", APP.delimiter)?,
+                },
+                _ => write!(out, "{}{}", APP.delimiter, value(*f, path, md))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn type_char(md: &Metadata) -> &'static str {
+    match md.file_type() {
+        x if x.is_file() => "f",
+        x if x.is_dir() => "d",
+        x if x.is_symlink() => "l",
+        x if x.is_block_device() => "b",
+        x if x.is_char_device() => "c",
+        x if x.is_fifo() => "p",
+        x if x.is_socket() => "s",
+        _ => "?",
+    }
+}
+
+#[cfg(target_family = "windows")]
+fn type_char(md: &Metadata) -> &'static str {
+    match md.file_type() {
+        x if x.is_file() => "f",
+        x if x.is_dir() => "d",
+        x if x.is_symlink() => "l",
+        _ => "?",
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn dev(md: &Metadata) -> u64 {
+    md.dev()
+}
+
+#[cfg(target_family = "windows")]
+fn dev(_md: &Metadata) -> u64 {
+    0
+}
+
+// like ls -l - type then rwx for user, group and other with s and t folded in
+fn symbolic_mode(md: &Metadata, mode: u32) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match type_char(md) {
+        "f" => '-',
+        "?" => '-',
+        t => t.chars().next().unwrap_or('-'),
+    });
+    for (shift, special, set) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 7;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 1 != 0, mode & special != 0) {
+            (true, true) => set,
+            (false, true) => set.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
+// UTC from epoch seconds without pulling in a date crate - days to civil
+// date per Howard Hinnant's algorithm
+fn iso_8601(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", y, m, d, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::ScratchDir;
+
+    fn render(t: &str, path: &Path) -> String {
+        let md = std::fs::symlink_metadata(path).unwrap();
+        let mut out = vec![];
+        t.parse::<Template>().unwrap().write(&mut out, path, &md).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn template_fields_widths_and_escapes() {
+        let dir = ScratchDir::new();
+        let f = dir.join("Data.CSV.GZ");
+        std::fs::write(&f, b"12345").unwrap();
+        assert_eq!(render("%f|%e|%y|%s|%-4s|%4s|%%\\t\\0\\n", &f), "Data.CSV.GZ|.csv.gz|f|5|5   |   5|%\t\0\n");
+        assert_eq!(render("%y [%e]", &dir), "d []");
+    }
+
+    #[test]
+    fn template_errors() {
+        for t in ["%q", "%T", "%Tx", "%", "%-", "a\\", "\\q"] {
+            assert!(t.parse::<Template>().is_err(), "{}", t);
+        }
+        assert!("%T@ %C+ %A@ %B+ %10p %-3f".parse::<Template>().is_ok());
+    }
+
+    #[test]
+    fn columns_by_name_in_order() {
+        let c: Columns = " group,inode , btime,".parse().unwrap();
+        assert_eq!(c.cols.iter().map(|x| x.0).collect::<Vec<_>>(), vec!["group", "inode", "epoch_birth"]);
+        assert!("".parse::<Columns>().unwrap().cols.is_empty());
+        assert!("inode,size".parse::<Columns>().is_err());
+    }
+
+    #[test]
+    fn iso_8601_utc() {
+        assert_eq!(iso_8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_8601(-1), "1969-12-31T23:59:59Z");
+        assert_eq!(iso_8601(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso_8601(1_709_210_096), "2024-02-29T12:34:56Z");
+        assert_eq!(iso_8601(4_102_444_800), "2100-01-01T00:00:00Z");
+    }
+}
@@ -0,0 +1,74 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::cli::APP;
+use crate::util::{show, write_atomic};
+use crate::{group_name, user_name, AllStats};
+
+// label values may hold any path so escape what the text format requires
+fn esc(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn gauge(w: &mut dyn Write, name: &str, help: &str) -> Result<()> {
+    writeln!(w, "# HELP {} {}", name, help)?;
+    writeln!(w, "# TYPE {} gauge", name)?;
+    Ok(())
+}
+
+/// Write the scan summary as metrics for the node_exporter textfile collector
+///
+/// The file is replaced atomically so the collector never reads a partial scrape.
+pub fn write_prom(path: &Path, stats: &AllStats, real: Duration, cpu: Duration) -> Result<()> {
+    write_atomic(path, |w| {
+        let root = show(&APP.dir);
+        let top = stats.dtree.get(&APP.dir);
+        let total = |f: fn(&crate::DirStats) -> u64| top.map(f).unwrap_or(0);
+
+        gauge(w, "du2_total_bytes", "Bytes of files and directories under the scanned root")?;
+        writeln!(w, "du2_total_bytes{{root=\"{}\"}} {}", esc(&root), total(|d| d.size_recursively))?;
+        gauge(w, "du2_total_files", "Files under the scanned root")?;
+        writeln!(w, "du2_total_files{{root=\"{}\"}} {}", esc(&root), total(|d| d.file_count_recursively))?;
+        gauge(w, "du2_total_dirs", "Directories under the scanned root")?;
+        writeln!(w, "du2_total_dirs{{root=\"{}\"}} {}", esc(&root), total(|d| d.dir_count_recursively))?;
+
+        gauge(w, "du2_user_bytes", "Bytes owned per user")?;
+        for (uid, c) in stats.user_map.iter() {
+            writeln!(w, "du2_user_bytes{{root=\"{}\",user=\"{}\",uid=\"{}\"}} {}", esc(&root), esc(&user_name(*uid)), uid, c.1)?;
+        }
+        gauge(w, "du2_user_files", "Entries owned per user")?;
+        for (uid, c) in stats.user_map.iter() {
+            writeln!(w, "du2_user_files{{root=\"{}\",user=\"{}\",uid=\"{}\"}} {}", esc(&root), esc(&user_name(*uid)), uid, c.0)?;
+        }
+        gauge(w, "du2_group_bytes", "Bytes owned per group")?;
+        for (gid, c) in stats.group_map.iter() {
+            writeln!(w, "du2_group_bytes{{root=\"{}\",group=\"{}\",gid=\"{}\"}} {}", esc(&root), esc(&group_name(*gid)), gid, c.1)?;
+        }
+        gauge(w, "du2_group_files", "Entries owned per group")?;
+        for (gid, c) in stats.group_map.iter() {
+            writeln!(w, "du2_group_files{{root=\"{}\",group=\"{}\",gid=\"{}\"}} {}", esc(&root), esc(&group_name(*gid)), gid, c.0)?;
+        }
+
+        let dirs: Vec<_> = stats.dtree.iter()
+            .filter(|(p, _)| p.strip_prefix(&APP.dir).map(|r| r.components().count() <= APP.prom_depth).unwrap_or(false))
+            .collect();
+        gauge(w, "du2_dir_bytes", "Recursive bytes per directory")?;
+        for (p, d) in dirs.iter() {
+            writeln!(w, "du2_dir_bytes{{root=\"{}\",path=\"{}\"}} {}", esc(&root), esc(&show(p)), d.size_recursively)?;
+        }
+        gauge(w, "du2_dir_files", "Recursive file count per directory")?;
+        for (p, d) in dirs.iter() {
+            writeln!(w, "du2_dir_files{{root=\"{}\",path=\"{}\"}} {}", esc(&root), esc(&show(p)), d.file_count_recursively)?;
+        }
+
+        gauge(w, "du2_scan_duration_seconds", "Wall clock time of the scan")?;
+        writeln!(w, "du2_scan_duration_seconds{{root=\"{}\"}} {:.3}", esc(&root), real.as_secs_f64())?;
+        gauge(w, "du2_scan_cpu_seconds", "Process cpu time of the scan")?;
+        writeln!(w, "du2_scan_cpu_seconds{{root=\"{}\"}} {:.3}", esc(&root), cpu.as_secs_f64())?;
+        writeln!(w, "# EOF")?;
+        Ok(())
+    })
+}
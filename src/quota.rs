@@ -0,0 +1,340 @@
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::cli::APP;
+use crate::util::{fmt_count, fmt_size, glob_to_re, parse_size, show};
+use crate::{group_name, user_name, AllStats};
+
+/// Exit status when only warning level limits are passed
+pub const QUOTA_WARN_EXIT: i32 = 5;
+/// Exit status when any critical level limit is passed
+pub const QUOTA_CRIT_EXIT: i32 = 6;
+
+#[derive(Deserialize)]
+struct QuotaFile {
+    limit: Vec<LimitSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitSpec {
+    path: Option<String>,
+    user: Option<String>,
+    group: Option<String>,
+    warn_size: Option<SizeSpec>,
+    crit_size: Option<SizeSpec>,
+    warn_files: Option<u64>,
+    crit_files: Option<u64>,
+}
+
+// sizes can be plain bytes or a string with a unit like "50G"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeSpec {
+    Bytes(u64),
+    Text(String),
+}
+
+impl SizeSpec {
+    fn bytes(&self) -> Result<u64> {
+        match self {
+            SizeSpec::Bytes(b) => Ok(*b),
+            SizeSpec::Text(s) => parse_size(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    // glob as a regex and whether it is matched against the full path
+    // or the path relative to DIRECTORY
+    Path(Regex, bool),
+    User(String),
+    Group(String),
+}
+
+#[derive(Debug, Clone)]
+struct Limit {
+    target: Target,
+    size: (Option<u64>, Option<u64>),
+    files: (Option<u64>, Option<u64>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Warning,
+    Critical,
+}
+
+pub struct Violation {
+    level: Level,
+    what: String,
+    metric: &'static str,
+    value: u64,
+    limit: u64,
+}
+
+impl Violation {
+    fn over_pct(&self) -> f64 {
+        (self.value - self.limit) as f64 * 100.0 / self.limit as f64
+    }
+
+    fn amount(&self, v: u64) -> String {
+        if self.metric == "size" {
+            fmt_size(v)
+        } else {
+            fmt_count(v)
+        }
+    }
+}
+
+/// Size and file count limits on directories, users and groups
+///
+/// Directory limits apply to the recursive totals of every directory
+/// the path glob matches, user and group limits to everything they own.
+#[derive(Debug, Clone)]
+pub struct Quotas {
+    limits: Vec<Limit>,
+}
+
+impl Quotas {
+    pub fn from_file(path: &Path) -> Result<Quotas> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read thresholds file: {}", path.display()))?;
+        Self::parse(&s).with_context(|| format!("in thresholds file: {}", path.display()))
+    }
+
+    fn parse(s: &str) -> Result<Quotas> {
+        let qf: QuotaFile = toml::from_str(s)?;
+        let mut limits = vec![];
+        for (i, l) in qf.limit.into_iter().enumerate() {
+            let target = match (l.path, l.user, l.group) {
+                (Some(p), None, None) => Target::Path(Regex::new(&glob_to_re(&p))?, p.starts_with('/')),
+                (None, Some(u), None) => Target::User(u),
+                (None, None, Some(g)) => Target::Group(g),
+                _ => return Err(anyhow!("limit {} must have exactly one of path, user or group", i + 1)),
+            };
+            let size = (l.warn_size.map(|x| x.bytes()).transpose()?, l.crit_size.map(|x| x.bytes()).transpose()?);
+            let files = (l.warn_files, l.crit_files);
+            if size == (None, None) && files == (None, None) {
+                return Err(anyhow!("limit {} has no warn_ or crit_ size or files", i + 1));
+            }
+            limits.push(Limit { target, size, files });
+        }
+        Ok(Quotas { limits })
+    }
+
+    pub fn check(&self, stats: &AllStats) -> Vec<Violation> {
+        self.check_in(stats, &APP.dir, show)
+    }
+
+    // path limits relative to dir, with paths written by show
+    fn check_in(&self, stats: &AllStats, dir: &Path, show: fn(&Path) -> Cow<'_, str>) -> Vec<Violation> {
+        let mut v = vec![];
+        for l in self.limits.iter() {
+            match &l.target {
+                Target::Path(re, absolute) => {
+                    for (p, ds) in stats.dtree.iter() {
+                        let s = match path_key(p, dir, *absolute) {
+                            Some(s) => s,
+                            None => continue,
+                        };
+                        if re.is_match(&s) {
+                            l.test(&show(p), ds.size_recursively, ds.file_count_recursively, &mut v);
+                        }
+                    }
+                }
+                Target::User(u) => {
+                    for (uid, c) in stats.user_map.iter() {
+                        let name = user_name(*uid);
+                        if u == "*" || *u == name || *u == uid.to_string() {
+                            l.test(&format!("user {}", name), c.1, stats.user_files.get(uid).copied().unwrap_or(0), &mut v);
+                        }
+                    }
+                }
+                Target::Group(g) => {
+                    for (gid, c) in stats.group_map.iter() {
+                        let name = group_name(*gid);
+                        if g == "*" || *g == name || *g == gid.to_string() {
+                            l.test(&format!("group {}", name), c.1, stats.group_files.get(gid).copied().unwrap_or(0), &mut v);
+                        }
+                    }
+                }
+            }
+        }
+        v.sort_by(|a, b| b.level.cmp(&a.level)
+            .then(b.over_pct().total_cmp(&a.over_pct()))
+            .then(a.what.cmp(&b.what)));
+        v
+    }
+}
+
+// what a path glob is matched against - the full path or the path relative
+// to DIRECTORY with "." for DIRECTORY itself
+fn path_key<'a>(p: &'a Path, dir: &Path, absolute: bool) -> Option<Cow<'a, str>> {
+    if absolute {
+        return Some(p.to_string_lossy());
+    }
+    match p.strip_prefix(dir) {
+        Ok(r) if r.as_os_str().is_empty() => Some(".".into()),
+        Ok(r) => Some(r.to_string_lossy()),
+        Err(_) => None,
+    }
+}
+
+impl Limit {
+    fn test(&self, what: &str, size: u64, files: u64, v: &mut Vec<Violation>) {
+        for (metric, value, (warn, crit)) in [("size", size, self.size), ("files", files, self.files)] {
+            let hit = match (warn, crit) {
+                (_, Some(c)) if value > c => Some((Level::Critical, c)),
+                (Some(w), _) if value > w => Some((Level::Warning, w)),
+                _ => None,
+            };
+            if let Some((level, limit)) = hit {
+                v.push(Violation { level, what: what.to_string(), metric, value, limit });
+            }
+        }
+    }
+}
+
+/// Exit status for a set of violations - 0 when there are none
+pub fn exit_code(v: &[Violation]) -> i32 {
+    match v.iter().map(|x| x.level).max() {
+        None => 0,
+        Some(Level::Warning) => QUOTA_WARN_EXIT,
+        Some(Level::Critical) => QUOTA_CRIT_EXIT,
+    }
+}
+
+pub fn print(out: &mut dyn Write, v: &[Violation]) -> Result<()> {
+    let crit = v.iter().filter(|x| x.level == Level::Critical).count();
+    writeln!(out, "\nQuota violations: {} critical  {} warning", crit, v.len() - crit)?;
+    for x in v {
+        let level = match x.level {
+            Level::Critical => "CRITICAL",
+            Level::Warning => "WARNING",
+        };
+        let pct = if x.limit == 0 { "-".to_string() } else { format!("{:.1}%", x.over_pct()) };
+        writeln!(out, "{:<8} {:<5} {:>14} limit {:>14} over by {:>14} {:>8} {}",
+                      level, x.metric, x.amount(x.value), x.amount(x.limit), x.amount(x.value - x.limit), pct, x.what)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violations(q: &Quotas, size: u64, files: u64) -> Vec<(Level, &'static str, u64)> {
+        let mut v = vec![];
+        q.limits[0].test("x", size, files, &mut v);
+        v.iter().map(|x| (x.level, x.metric, x.limit)).collect()
+    }
+
+    #[test]
+    fn parse_targets_and_sizes() {
+        let q = Quotas::parse(r#"
+            [[limit]]
+            path = "/home/*"
+            warn_size = "1K"
+            crit_size = 4096
+            [[limit]]
+            path = "scratch/**"
+            crit_files = 10
+            [[limit]]
+            user = "*"
+            warn_files = 5
+            [[limit]]
+            group = "staff"
+            crit_size = "2M"
+        "#).unwrap();
+        assert_eq!(q.limits.len(), 4);
+        assert!(matches!(&q.limits[0].target, Target::Path(re, true) if re.is_match("/home/a") && !re.is_match("/home/a/b")));
+        assert!(matches!(&q.limits[1].target, Target::Path(re, false) if re.is_match("scratch/a/b")));
+        assert!(matches!(&q.limits[2].target, Target::User(u) if u == "*"));
+        assert!(matches!(&q.limits[3].target, Target::Group(g) if g == "staff"));
+        assert_eq!(q.limits[0].size, (Some(1024), Some(4096)));
+        assert_eq!(q.limits[1].files, (None, Some(10)));
+        assert_eq!(q.limits[3].size, (None, Some(2 << 20)));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in [
+            "[[limit]]\nwarn_size = 1",
+            "[[limit]]\npath = \"a\"\nuser = \"b\"\nwarn_size = 1",
+            "[[limit]]\npath = \"a\"",
+            "[[limit]]\npath = \"a\"\nwarn_size = \"lots\"",
+            "[[limit]]\npath = \"a\"\nwarn_size = 1\nsoft = 2",
+            "limit = 3",
+        ] {
+            assert!(Quotas::parse(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn critical_wins_over_warning() {
+        let q = Quotas::parse("[[limit]]\nuser = \"*\"\nwarn_size = 100\ncrit_size = 200\nwarn_files = 3").unwrap();
+        assert_eq!(violations(&q, 100, 3), vec![]);
+        assert_eq!(violations(&q, 101, 3), vec![(Level::Warning, "size", 100)]);
+        assert_eq!(violations(&q, 201, 4), vec![(Level::Critical, "size", 200), (Level::Warning, "files", 3)]);
+    }
+
+    #[test]
+    fn exit_code_is_worst_level() {
+        let q = Quotas::parse("[[limit]]\nuser = \"*\"\nwarn_size = 10\ncrit_files = 10").unwrap();
+        let mut v = vec![];
+        assert_eq!(exit_code(&v), 0);
+        q.limits[0].test("x", 11, 0, &mut v);
+        assert_eq!(exit_code(&v), QUOTA_WARN_EXIT);
+        q.limits[0].test("y", 0, 11, &mut v);
+        assert_eq!(exit_code(&v), QUOTA_CRIT_EXIT);
+        assert_eq!(v[0].over_pct(), 10.0);
+    }
+
+    fn plain(p: &Path) -> Cow<'_, str> {
+        p.to_string_lossy()
+    }
+
+    #[test]
+    fn check_users_by_files_and_dirs_by_totals() {
+        let q = Quotas::parse(r#"
+            [[limit]]
+            user = "0"
+            warn_files = 2
+            [[limit]]
+            path = "a"
+            crit_size = 10
+            [[limit]]
+            path = "."
+            crit_size = 100
+        "#).unwrap();
+        let mut stats = AllStats::new();
+        // directories are among the entries a user owns but not their files
+        stats.user_map.insert(0, (5, 50));
+        stats.user_files.insert(0, 3);
+        let mut a = crate::DirStats::new();
+        a.size_recursively = 20;
+        stats.dtree.insert("/d/a".into(), a.clone());
+        stats.dtree.insert("/d/b".into(), a);
+        stats.dtree.insert("/d".into(), crate::DirStats::new());
+        let v = q.check_in(&stats, Path::new("/d"), plain);
+        let got: Vec<_> = v.iter().map(|x| (x.level, x.what.as_str(), x.metric, x.value)).collect();
+        let user = format!("user {}", user_name(0));
+        assert_eq!(got, vec![(Level::Critical, "/d/a", "size", 20), (Level::Warning, user.as_str(), "files", 3)]);
+    }
+
+    #[test]
+    fn path_key_relative_to_dir() {
+        let dir = Path::new("/data");
+        assert_eq!(path_key(Path::new("/data"), dir, false).as_deref(), Some("."));
+        assert_eq!(path_key(Path::new("/data/a/b"), dir, false).as_deref(), Some("a/b"));
+        assert_eq!(path_key(Path::new("/database"), dir, false), None);
+        assert_eq!(path_key(Path::new("/data/a"), dir, true).as_deref(), Some("/data/a"));
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// tar puts its magic after the 257 byte name/mode/size header block
+const SNIFF_LEN: usize = 264;
+
+const MAGIC: &[(usize, &[u8], &str)] = &[
+    (0, &[0x1f, 0x8b], "gzip"),
+    (0, &[0x28, 0xb5, 0x2f, 0xfd], "zstd"),
+    (0, b"\x7fELF", "elf"),
+    (0, b"\x89PNG\r\n\x1a\n", "png"),
+    (0, &[0xff, 0xd8, 0xff], "jpeg"),
+    (0, b"%PDF-", "pdf"),
+    (0, b"SQLite format 3\0", "sqlite"),
+    (0, b"\x89HDF\r\n\x1a\n", "hdf5"),
+    (0, b"PAR1", "parquet"),
+    (257, b"ustar", "tar"),
+];
+
+/// Identify a file's format from its first few bytes
+///
+/// Returns "empty" for zero length files, "unreadable" when the file
+/// cannot be opened or read and "unknown" when no magic number matches.
+pub fn sniff(path: &Path, len: u64) -> &'static str {
+    if len == 0 {
+        return "empty";
+    }
+    let mut buf = [0u8; SNIFF_LEN];
+    let mut got = 0;
+    match File::open(path) {
+        Err(_) => return "unreadable",
+        Ok(mut f) => {
+            // short reads are legal so loop until full or EOF
+            while got < buf.len() {
+                match f.read(&mut buf[got..]) {
+                    Ok(0) => break,
+                    Ok(n) => got += n,
+                    Err(_) => return "unreadable",
+                }
+            }
+        }
+    }
+    let head = &buf[..got];
+    for (off, magic, name) in MAGIC {
+        if head.len() >= off + magic.len() && &head[*off..off + magic.len()] == *magic {
+            return name;
+        }
+    }
+    "unknown"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::ScratchDir;
+
+    #[test]
+    fn magic_numbers() {
+        let dir = ScratchDir::new();
+        let mut tar = vec![0u8; 512];
+        tar[257..263].copy_from_slice(b"ustar\0");
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x1f, 0x8b, 8, 0], "gzip"),
+            (b"%PDF-1.7\n", "pdf"),
+            (b"PAR1", "parquet"),
+            (&tar, "tar"),
+            // the tar magic is past the end of a short file
+            (&tar[..260], "unknown"),
+            (b"plain text", "unknown"),
+            (b"\x7fEL", "unknown"),
+        ];
+        for (i, (data, want)) in cases.iter().enumerate() {
+            let f = dir.join(i.to_string());
+            std::fs::write(&f, data).unwrap();
+            assert_eq!(sniff(&f, data.len() as u64), *want, "case {}", i);
+        }
+        assert_eq!(sniff(&dir.join("0"), 0), "empty");
+        assert_eq!(sniff(&dir.join("missing"), 1), "unreadable");
+    }
+}
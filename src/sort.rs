@@ -0,0 +1,278 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+// bookkeeping per entry on top of its bytes when counting memory
+const ENTRY_OVERHEAD: usize = 24;
+// files kept free for everything else when runs are merged
+const RESERVED_FILES: u64 = 64;
+// most runs merged at once however high the file limit
+const MAX_MERGE_WIDTH: u64 = 256;
+
+/// Gathers listing entries and writes them in path order
+///
+/// Entries are kept in memory until --sort-memory is used up, then sorted
+/// and spilled as a run to a temp file.  The runs are merged at the end,
+/// in several passes when there are more than can be open at once.
+pub struct Sorter {
+    buf: Vec<u8>,
+    // start of the key, start of the entry and its end within buf
+    recs: Vec<(usize, usize, usize)>,
+    open: Option<(usize, usize)>,
+    runs: Vec<PathBuf>,
+    made: usize,
+    memory: usize,
+    dir: PathBuf,
+    width: usize,
+}
+
+/// Where the entry for `path` should be written - the sorter when
+/// sorting and straight to the listing otherwise
+pub fn entry<'a>(sorter: &'a mut Option<Sorter>, out: &'a mut dyn Write, path: &Path) -> Result<&'a mut dyn Write> {
+    match sorter {
+        Some(s) => {
+            s.key(path)?;
+            Ok(s)
+        }
+        None => Ok(out),
+    }
+}
+
+// separators become NUL so a plain byte compare puts a directory's
+// contents right after it and before any sibling sharing its name as a prefix
+#[cfg(target_family = "unix")]
+fn key_bytes(path: &Path, buf: &mut Vec<u8>) {
+    use std::os::unix::ffi::OsStrExt;
+    buf.extend(path.as_os_str().as_bytes().iter().map(|&b| if b == b'/' { 0 } else { b }));
+}
+
+#[cfg(target_family = "windows")]
+fn key_bytes(path: &Path, buf: &mut Vec<u8>) {
+    buf.extend(path.to_string_lossy().bytes().map(|b| if b == b'\\' || b == b'/' { 0 } else { b }));
+}
+
+// runs that can be open at once within the soft open file limit
+#[cfg(target_family = "unix")]
+fn merge_width() -> usize {
+    let mut lim = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    let cur = if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut lim) } == 0 { lim.rlim_cur } else { 1024 };
+    cur.saturating_sub(RESERVED_FILES).clamp(2, MAX_MERGE_WIDTH) as usize
+}
+
+#[cfg(target_family = "windows")]
+fn merge_width() -> usize {
+    MAX_MERGE_WIDTH as usize
+}
+
+impl Sorter {
+    /// Sorter holding about `memory` bytes before spilling runs into `dir`
+    pub fn new(memory: usize, dir: PathBuf) -> Sorter {
+        Sorter {
+            buf: vec![],
+            recs: vec![],
+            open: None,
+            runs: vec![],
+            made: 0,
+            memory,
+            dir,
+            width: merge_width(),
+        }
+    }
+
+    fn key(&mut self, path: &Path) -> Result<()> {
+        self.close();
+        if self.buf.len() + self.recs.len() * ENTRY_OVERHEAD >= self.memory {
+            self.spill()?;
+        }
+        let k = self.buf.len();
+        key_bytes(path, &mut self.buf);
+        self.open = Some((k, self.buf.len()));
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Some((k, r)) = self.open.take() {
+            self.recs.push((k, r, self.buf.len()));
+        }
+    }
+
+    fn sort(&mut self) {
+        let buf = &self.buf;
+        self.recs.sort_by(|a, b| buf[a.0..a.1].cmp(&buf[b.0..b.1]));
+    }
+
+    // a new empty run file that is removed with the sorter
+    fn new_run(&mut self) -> Result<BufWriter<File>> {
+        let path = self.dir.join(format!("du2-sort-{}-{}.tmp", std::process::id(), self.made));
+        self.made += 1;
+        let f = OpenOptions::new().write(true).create_new(true).open(&path)
+            .with_context(|| format!("cannot create sort run: {}", path.display()))?;
+        self.runs.push(path);
+        Ok(BufWriter::new(f))
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        self.sort();
+        let mut w = self.new_run()?;
+        for &(k, r, e) in self.recs.iter() {
+            write_rec(&mut w, &self.buf[k..r], &self.buf[r..e])?;
+        }
+        w.flush()?;
+        self.buf.clear();
+        self.recs.clear();
+        Ok(())
+    }
+
+    // merge the runs in groups of width into fewer, longer runs
+    fn merge_pass(&mut self, old: &[PathBuf]) -> Result<()> {
+        for group in old.chunks(self.width) {
+            let mut w = self.new_run()?;
+            merge_runs(group, &mut w, true)?;
+            w.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self, out: &mut dyn Write) -> Result<()> {
+        self.close();
+        if self.runs.is_empty() {
+            self.sort();
+            for &(_, r, e) in self.recs.iter() {
+                out.write_all(&self.buf[r..e])?;
+            }
+            return Ok(());
+        }
+        if !self.recs.is_empty() {
+            self.spill()?;
+        }
+        while self.runs.len() > self.width {
+            let old = std::mem::take(&mut self.runs);
+            let res = self.merge_pass(&old);
+            for p in old.iter() {
+                let _ = std::fs::remove_file(p);
+            }
+            res?;
+        }
+        merge_runs(&self.runs, out, false)
+    }
+}
+
+impl Write for Sorter {
+    // only reached through entry() so there is always an entry open
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Sorter {
+    fn drop(&mut self) {
+        for p in self.runs.iter() {
+            let _ = std::fs::remove_file(p);
+        }
+    }
+}
+
+// merge sorted runs into out as bare entries, or as whole records when
+// the output is another run
+fn merge_runs(runs: &[PathBuf], out: &mut dyn Write, records: bool) -> Result<()> {
+    let mut readers = vec![];
+    for p in runs.iter() {
+        readers.push(BufReader::new(File::open(p).with_context(|| format!("cannot open sort run: {}", p.display()))?));
+    }
+    // smallest key first and the earlier run on ties
+    let mut heap = BinaryHeap::new();
+    for (i, r) in readers.iter_mut().enumerate() {
+        if let Some((k, rec)) = read_rec(r)? {
+            heap.push(Reverse((k, i, rec)));
+        }
+    }
+    while let Some(Reverse((k, i, rec))) = heap.pop() {
+        if records {
+            write_rec(out, &k, &rec)?;
+        } else {
+            out.write_all(&rec)?;
+        }
+        if let Some((k, rec)) = read_rec(&mut readers[i])? {
+            heap.push(Reverse((k, i, rec)));
+        }
+    }
+    Ok(())
+}
+
+fn write_rec(w: &mut dyn Write, k: &[u8], rec: &[u8]) -> Result<()> {
+    w.write_all(&(k.len() as u32).to_le_bytes())?;
+    w.write_all(k)?;
+    w.write_all(&(rec.len() as u32).to_le_bytes())?;
+    w.write_all(rec)?;
+    Ok(())
+}
+
+fn read_rec(r: &mut BufReader<File>) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        x => x?,
+    }
+    let mut k = vec![0; u32::from_le_bytes(len) as usize];
+    r.read_exact(&mut k)?;
+    r.read_exact(&mut len)?;
+    let mut rec = vec![0; u32::from_le_bytes(len) as usize];
+    r.read_exact(&mut rec)?;
+    Ok(Some((k, rec)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::ScratchDir;
+
+    // sort the paths with the given memory and merge width, returning
+    // the output and what was left in the run directory
+    fn sorted(paths: &[&str], memory: usize, width: usize) -> (String, usize) {
+        let dir = ScratchDir::new();
+        let mut s = Some(Sorter::new(memory, dir.to_path_buf()));
+        s.as_mut().unwrap().width = width;
+        let mut out = vec![];
+        for p in paths {
+            let w = entry(&mut s, &mut out, Path::new(p)).unwrap();
+            writeln!(w, "{}", p).unwrap();
+        }
+        s.unwrap().finish(&mut out).unwrap();
+        let left = std::fs::read_dir(&*dir).unwrap().count();
+        (String::from_utf8(out).unwrap(), left)
+    }
+
+    const PATHS: [&str; 8] = ["/a/b-c", "/a/b/z", "/b", "/a/b", "/a", "/a/b/a", "/a-b", "/a/b-c/d"];
+    const EXPECT: &str = "/a\n/a/b\n/a/b/a\n/a/b/z\n/a/b-c\n/a/b-c/d\n/a-b\n/b\n";
+
+    #[test]
+    fn in_memory_puts_children_after_their_parent() {
+        assert_eq!(sorted(&PATHS, 1 << 20, 16), (EXPECT.to_string(), 0));
+    }
+
+    #[test]
+    fn spilled_runs_merge_in_one_pass() {
+        // every entry past the first spills the ones before it
+        assert_eq!(sorted(&PATHS, 1, 16), (EXPECT.to_string(), 0));
+    }
+
+    #[test]
+    fn spilled_runs_merge_in_several_passes() {
+        assert_eq!(sorted(&PATHS, 1, 2), (EXPECT.to_string(), 0));
+        assert_eq!(sorted(&PATHS, 40, 3), (EXPECT.to_string(), 0));
+    }
+
+    #[test]
+    fn merge_width_leaves_room() {
+        assert!(merge_width() >= 2 && merge_width() <= MAX_MERGE_WIDTH as usize);
+    }
+}
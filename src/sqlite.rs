@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+
+use crate::cli::APP;
+use crate::util::{ext_key, micros, show, temp_path, unix_fields};
+use crate::{AllStats, MetaList};
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = OFF;
+PRAGMA synchronous = OFF;
+CREATE TABLE files (
+    path TEXT NOT NULL,
+    parent TEXT NOT NULL,
+    name TEXT NOT NULL,
+    extension TEXT,
+    type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    blocks INTEGER NOT NULL,
+    uid INTEGER NOT NULL,
+    gid INTEGER NOT NULL,
+    mode INTEGER NOT NULL,
+    mtime INTEGER,
+    atime INTEGER,
+    ctime INTEGER,
+    inode INTEGER NOT NULL,
+    nlink INTEGER NOT NULL
+);
+CREATE TABLE dirs (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    parent_id INTEGER REFERENCES dirs(id),
+    size_direct INTEGER NOT NULL,
+    size_recursive INTEGER NOT NULL,
+    files_direct INTEGER NOT NULL,
+    files_recursive INTEGER NOT NULL,
+    dirs_direct INTEGER NOT NULL,
+    dirs_recursive INTEGER NOT NULL,
+    slack_direct INTEGER NOT NULL,
+    oldest_mtime_direct INTEGER,
+    newest_mtime_direct INTEGER,
+    oldest_mtime_recursive INTEGER,
+    newest_mtime_recursive INTEGER
+);
+CREATE TABLE scan (
+    root TEXT NOT NULL,
+    started INTEGER NOT NULL,
+    real_seconds REAL NOT NULL,
+    cpu_seconds REAL NOT NULL,
+    entries INTEGER NOT NULL,
+    total_bytes INTEGER NOT NULL,
+    version TEXT NOT NULL
+);
+BEGIN;
+";
+
+/// Writes the scan to a SQLite database - files as they are found and
+/// the directory tree and scan details once it is complete
+///
+/// Built under a temp name in one transaction and renamed into place at
+/// the end.  Times are seconds since the epoch.
+pub struct SqliteOut {
+    conn: Option<Connection>,
+    tmp: PathBuf,
+    path: PathBuf,
+}
+
+impl SqliteOut {
+    pub fn new(path: &Path) -> Result<SqliteOut> {
+        let tmp = temp_path(path)?;
+        // a leftover from a run that died would already have the tables
+        let _ = std::fs::remove_file(&tmp);
+        let conn = Connection::open(&tmp).with_context(|| format!("cannot create database: {}", tmp.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteOut { conn: Some(conn), tmp, path: path.to_path_buf() })
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.as_ref().expect("database already finished")
+    }
+
+    pub fn add_files(&mut self, list: &MetaList) -> Result<()> {
+        let mut st = self.conn().prepare_cached("INSERT INTO files VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)")?;
+        for (path, md, _) in list {
+            let ft = md.file_type();
+            if !ft.is_file() && !ft.is_dir() {
+                continue;
+            }
+            let f_age = md.modified()?;
+            if !(APP.file_newer_than.is_none_or(|x| x < f_age) && APP.file_older_than.is_none_or(|x| x > f_age)) {
+                continue;
+            }
+            let (blocks, uid, gid, mode, ctime, inode, nlink) = unix_fields(md);
+            let secs = |t: Option<i64>| t.map(|t| t.div_euclid(1_000_000));
+            st.execute(params![
+                show(path),
+                path.parent().map(show).unwrap_or_default(),
+                path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(),
+                if ft.is_file() { ext_key(path) } else { None },
+                if ft.is_file() { "f" } else { "d" },
+                md.len() as i64,
+                blocks,
+                uid,
+                gid,
+                mode,
+                secs(micros(md.modified())),
+                secs(micros(md.accessed())),
+                secs(ctime),
+                inode,
+                nlink,
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self, stats: &AllStats) -> Result<()> {
+        {
+            let conn = self.conn();
+            let started = stats.started.duration_since(UNIX_EPOCH).unwrap_or_default();
+            // ages are kept relative to the start of the scan
+            let mtime = |age: Option<Duration>| age
+                .and_then(|a| stats.started.checked_sub(a))
+                .and_then(|t| micros(Ok(t)))
+                .map(|t| t.div_euclid(1_000_000));
+
+            let mut ids: HashMap<&Path, i64> = HashMap::with_capacity(stats.dtree.len());
+            let mut st = conn.prepare("INSERT INTO dirs VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)")?;
+            // sorted so every parent is in before its children
+            for (id, (p, ds)) in stats.dtree.iter().enumerate() {
+                let id = id as i64 + 1;
+                let parent = p.parent().and_then(|par| ids.get(par)).copied();
+                let ages = &ds.age_range;
+                st.execute(params![
+                    id,
+                    show(p),
+                    parent,
+                    ds.size_directly as i64,
+                    ds.size_recursively as i64,
+                    ds.file_count_directly as i64,
+                    ds.file_count_recursively as i64,
+                    ds.dir_count_directly as i64,
+                    ds.dir_count_recursively as i64,
+                    ds.slack_directly as i64,
+                    mtime(ages.oldest_file_direct),
+                    mtime(ages.newest_file_direct),
+                    mtime(ages.oldest_file_recursive),
+                    mtime(ages.newest_file_recursive),
+                ])?;
+                ids.insert(p, id);
+            }
+
+            let (entries, real, cpu) = stats.scanned;
+            conn.execute("INSERT INTO scan VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)", params![
+                show(&APP.dir),
+                started.as_secs() as i64,
+                real.as_secs_f64(),
+                cpu.as_secs_f64(),
+                entries as i64,
+                stats.total_usage as i64,
+                env!("CARGO_PKG_VERSION"),
+            ])?;
+            conn.execute_batch("
+                CREATE INDEX files_parent ON files(parent);
+                CREATE INDEX dirs_parent ON dirs(parent_id);
+                COMMIT;
+            ")?;
+        }
+        if let Some(conn) = self.conn.take() {
+            conn.close().map_err(|(_, e)| e)?;
+        }
+        std::fs::rename(&self.tmp, &self.path)
+            .with_context(|| format!("cannot rename {} to {}", self.tmp.display(), self.path.display()))?;
+        Ok(())
+    }
+}
+
+impl Drop for SqliteOut {
+    // never finished so something failed - leave no temp file behind
+    fn drop(&mut self) {
+        if self.conn.take().is_some() {
+            let _ = std::fs::remove_file(&self.tmp);
+        }
+    }
+}
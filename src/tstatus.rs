@@ -45,28 +45,37 @@ impl ThreadStatus {
     }
 }
 
+// clones share the list so threads set up later are seen by all of them
+#[derive(Clone)]
 pub struct ThreadTracker {
-    list: Vec<ThreadStatus>,
+    list: Arc<Mutex<Vec<ThreadStatus>>>,
 }
 
 impl ThreadTracker {
     pub fn new() -> ThreadTracker {
         ThreadTracker {
-            list: Vec::new(),
+            list: Arc::new(Mutex::new(Vec::new())),
         }
     }
     pub fn setup_thread(&mut self, name: &str, state: &str) -> ThreadStatus {
         let ts = ThreadStatus::new(state, name);
         let cl = ts.clone();
-        self.list.push(ts);
+        self.list.lock().unwrap().push(ts);
         cl
     }
     pub fn eprint_status(&self) {
-        for ts in self.list.iter().enumerate() {
+        for ts in self.list.lock().unwrap().iter().enumerate() {
             let g_ts = ts.1.status.lock().unwrap();
             eprintln!("index: {:2} {:<10} tid: {:6}  status: \"{}\"", ts.0, &g_ts.name, &g_ts.tid, &g_ts.state);
         }
     }
+    /// name, tid and state of every thread set up so far
+    pub fn status_list(&self) -> Vec<(String, usize, String)> {
+        self.list.lock().unwrap().iter().map(|ts| {
+            let g_ts = ts.status.lock().unwrap();
+            (g_ts.name.clone(), g_ts.tid, g_ts.state.clone())
+        }).collect()
+    }
 
     // once you start monitor you can no longer add/change it
     pub fn monitor(&self, interval_ms: u64) {
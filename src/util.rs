@@ -1,101 +1,341 @@
 #![allow(dead_code)]
 
 use std::borrow::Cow;
-use std::path::Path;
-use std::time::Duration;
+use std::fs::{rename, File, Metadata};
+use std::io::{BufWriter, Write};
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use num_format::{Locale, ToFormattedString};
+
+use crate::cli::APP;
+
+/// A path as it is written out - relative to DIRECTORY with --relative
+/// or with the --rewrite-prefix prefix swapped
+pub fn show(p: &Path) -> Cow<'_, str> {
+    show_as(p, &APP.dir, APP.relative, APP.rewrite_prefix.as_ref())
+}
+
+/// A path below DIRECTORY as a relative one, "." for DIRECTORY itself
+pub fn relative(p: &Path) -> Cow<'_, str> {
+    show_as(p, &APP.dir, true, None)
+}
+
+/// The scanned path behind one written by show(), for paths handed back
+/// by users - relative ones are taken as below DIRECTORY
+pub fn unshow(s: &str) -> PathBuf {
+    unshow_as(s, &APP.dir, APP.rewrite_prefix.as_ref())
+}
+
+fn show_as<'a>(p: &'a Path, dir: &Path, relative: bool, rewrite: Option<&'a (PathBuf, String)>) -> Cow<'a, str> {
+    if relative {
+        match p.strip_prefix(dir) {
+            Ok(r) if r.as_os_str().is_empty() => return Cow::Borrowed("."),
+            Ok(r) => return r.to_string_lossy(),
+            Err(_) => {}
+        }
+    } else if let Some((from, to)) = rewrite {
+        match p.strip_prefix(from) {
+            Ok(r) if r.as_os_str().is_empty() => return Cow::Borrowed(to),
+            Ok(r) => return Cow::Owned(format!("{}/{}", to.trim_end_matches('/'), r.to_string_lossy())),
+            Err(_) => {}
+        }
+    }
+    p.to_string_lossy()
+}
+
+fn unshow_as(s: &str, dir: &Path, rewrite: Option<&(PathBuf, String)>) -> PathBuf {
+    if let Some((from, to)) = rewrite {
+        let to_dir = to.trim_end_matches('/');
+        if s == to || s == to_dir {
+            return from.clone();
+        }
+        if let Some(r) = s.strip_prefix(to_dir).and_then(|r| r.strip_prefix('/')) {
+            return from.join(r);
+        }
+    }
+    match Path::new(s) {
+        p if p.is_absolute() => p.to_path_buf(),
+        _ if s.is_empty() || s == "." => dir.to_path_buf(),
+        p => dir.join(p.strip_prefix(".").unwrap_or(p)),
+    }
+}
 
 // Cow here let's us not allocate in the common case
-pub fn multi_extension(p: & Path) -> Option<Cow<str>> {
-    if let Some(filename) = p.to_str() {
-        if filename.len() > 0 {
-            let mut last_i = filename.len() - 1;
-            for x in filename.chars().rev().zip((0..filename.len()).rev()) {
-                // println!("i: {} {}  lasti: {}", x.0, x.1, last_i);
-
-                if (last_i - x.1) > 4 {
-                    break;
-                } else if x.0 == '.' {
-                    last_i = x.1;
-                }
-            }
+// the last dot separated part of the file name, plus the part before it when
+// the last is a compression suffix so .tar.zst and .csv.gz stay whole
+pub fn multi_extension(p: & Path) -> Option<Cow<'_, str>> {
+    const COMPRESSED: [&str; 8] = ["gz", "bz2", "xz", "zst", "lz4", "lzma", "z", "br"];
+    // dots in ordinary names are not extensions
+    fn is_ext(s: &str) -> bool {
+        !s.is_empty() && s.len() <= 16 && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+    }
 
-            if last_i != filename.len() - 1 {
-                return Some(Cow::Borrowed(&&filename[last_i..]));
-            } else {
-                return None;
+    let filename = p.file_name()?.to_str()?;
+    let dot = filename.rfind('.').filter(|&i| i > 0)?;
+    if !is_ext(&filename[dot + 1..]) {
+        return None;
+    }
+    let mut start = dot;
+    if COMPRESSED.iter().any(|c| filename[dot + 1..].eq_ignore_ascii_case(c)) {
+        if let Some(prev) = filename[..dot].rfind('.').filter(|&i| i > 0) {
+            if is_ext(&filename[prev + 1..dot]) {
+                start = prev;
             }
         }
     }
-    None
+    Some(Cow::Borrowed(&filename[start..]))
 }
 
-fn mem_metric<'a>(v: usize) -> (f64, &'a str) {
-    const METRIC: [&str; 8] = ["B ", "KB", "MB", "GB", "TB", "PB", "EB", "ZB"];
+/// Extension as every report and listing keys it - the multi_extension
+/// folded to lower case so .JPG and .jpg are the same thing
+pub fn ext_key(p: &Path) -> Option<Cow<'_, str>> {
+    let ext = multi_extension(p)?;
+    Some(if ext.chars().any(|c| c.is_uppercase()) { Cow::Owned(ext.to_lowercase()) } else { ext })
+}
 
-    let mut size = 1usize << 10;
-    for e in &METRIC {
-        if v < size {
-            return ((v as f64 / (size >> 10) as f64) as f64, e);
-        }
-        size <<= 10;
-    }
-    (v as f64, "")
-}
-
-/// keep only a few significant digits of a simple float value
-fn sig_dig(v: f64, digits: usize) -> String {
-    let x = format!("{}", v);
-    let mut d = String::new();
-    let mut count = 0;
-    let mut found_pt = false;
-    for c in x.chars() {
-        if c != '.' {
-            count += 1;
-        } else {
-            if count >= digits {
-                break;
+// * and ? stay within one path component while ** crosses them, [...] is a
+// class and like the shell names starting with a dot need the dot spelled out
+pub fn glob_to_re(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        let at_name = re.ends_with('^') || re.ends_with('/');
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' if at_name => re.push_str("(?:[^./][^/]*)?"),
+            '?' if at_name => re.push_str("[^./]"),
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                let mut raw = String::from("[");
+                let mut class = String::from("[");
+                let mut closed = false;
+                for (i, c) in chars.by_ref().enumerate() {
+                    raw.push(c);
+                    match c {
+                        ']' if i > 0 => { closed = true; break; }
+                        '!' | '^' if i == 0 => class.push('^'),
+                        '\\' | '[' | '&' | '~' => { class.push('\\'); class.push(c); }
+                        _ => class.push(c),
+                    }
+                }
+                if closed {
+                    re.push_str(&class);
+                    re.push(']');
+                } else {
+                    // no closing ] so it is a plain [ like the shell
+                    re.push_str(&regex::escape(&raw));
+                }
             }
-            found_pt = true;
+            _ => re.push_str(&regex::escape(&c.to_string())),
         }
+    }
+    re.push('$');
+    re
+}
+
+/// How sizes and counts are written in reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Units {
+    // scaled by 1024 as KiB, MiB...
+    #[default]
+    Iec,
+    // scaled by 1000 as kB, MB...
+    Si,
+    // whole blocks of a fixed size, rounded up
+    Block(u64),
+    // plain numbers for scripts - bytes and counts without separators
+    Bytes,
+}
 
-        d.push(c);
+impl FromStr for Units {
+    type Err = anyhow::Error;
 
-        if count >= digits && found_pt {
-            break;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "iec" => Ok(Units::Iec),
+            "si" => Ok(Units::Si),
+            "bytes" => Ok(Units::Bytes),
+            _ => Err(anyhow!("unknown units \"{}\", expected iec, si or bytes", s)),
         }
     }
-    d
 }
 
-pub fn mem_metric_digit(v: usize, sig: usize) -> String {
-    if v == 0 || v > std::usize::MAX / 2 {
-        return format!("{:>width$}", "unknown", width = sig + 3);
+const IEC_SUFFIXES: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+const SI_SUFFIXES: &[&str] = &["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+
+// three significant digits with the suffix in a column of its own
+fn scaled(v: u64, base: f64, suffixes: &[&str]) -> String {
+    let mut number = v as f64;
+    let mut multi = 0;
+    while number >= base && multi < suffixes.len() - 1 {
+        multi += 1;
+        number /= base;
     }
-    let vt = mem_metric(v);
-    format!("{:>width$} {}", sig_dig(vt.0, sig), vt.1, width = sig + 1, )
+    let n = match number {
+        _ if multi == 0 => format!("{}", v),
+        x if x < 10.0 => format!("{:.2}", x),
+        x if x < 100.0 => format!("{:.1}", x),
+        x => format!("{:.0}", x),
+    };
+    format!("{:<5}{}", n, suffixes[multi])
 }
 
-const GREEK_SUFFIXES: &[&str] = &["B", "KB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
+/// A size in bytes in the --units chosen
+pub fn fmt_size(v: u64) -> String {
+    fmt_size_as(v, APP.units)
+}
 
-pub fn greek(v: f64) -> String {
-    let mut number = v;
-    let mut multi = 0;
+/// A count with thousands separators unless --units is bytes
+pub fn fmt_count<T: ToFormattedString + ToString>(v: T) -> String {
+    fmt_count_as(v, APP.units)
+}
 
-    while number >= 1000.0 && multi < GREEK_SUFFIXES.len() - 1 {
-        multi += 1;
-        number /= 1024.0;
+fn fmt_size_as(v: u64, units: Units) -> String {
+    match units {
+        Units::Iec => scaled(v, 1024.0, IEC_SUFFIXES),
+        Units::Si => scaled(v, 1000.0, SI_SUFFIXES),
+        Units::Block(b) => format!("{} {}", fmt_count_as(v.div_ceil(b), units), block_label(b)),
+        Units::Bytes => v.to_string(),
+    }
+}
+
+fn fmt_count_as<T: ToFormattedString + ToString>(v: T, units: Units) -> String {
+    match units {
+        Units::Bytes => v.to_string(),
+        _ => v.to_formatted_string(&Locale::en),
+    }
+}
+
+// a block that is a unit of its own reads as that unit, others like df's 4K-blocks
+fn block_label(b: u64) -> String {
+    let iec = IEC_SUFFIXES.iter().enumerate().find(|(i, _)| 1u64 << (10 * i) == b);
+    let si = SI_SUFFIXES.iter().enumerate().find(|(i, _)| 1000u64.pow(*i as u32) == b);
+    if let Some((_, s)) = iec.or(si) {
+        return s.to_string();
+    }
+    let (n, unit) = ["E", "P", "T", "G", "M", "K"].iter().enumerate()
+        .map(|(i, u)| (1u64 << (10 * (6 - i)), u))
+        .find(|(m, _)| b.is_multiple_of(*m))
+        .map(|(m, u)| (b / m, *u))
+        .unwrap_or((b, ""));
+    format!("{}{}-blocks", n, unit)
+}
+
+/// Parse a size like 1500, 64K, 2.5G or 1TB where each unit is 1024 of the one below
+pub fn parse_size(s: &str) -> Result<u64> {
+    let t = s.trim();
+    let digits = t.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(t.len());
+    let (num, unit) = t.split_at(digits);
+    let num: f64 = num.parse().with_context(|| format!("invalid size: \"{}\"", s))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let unit = unit.strip_suffix("IB").or_else(|| unit.strip_suffix('B')).unwrap_or(&unit);
+    let power = match unit {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        "E" => 6,
+        _ => return Err(anyhow::anyhow!("unknown size unit in \"{}\", expected K, M, G, T, P or E", s)),
+    };
+    Ok((num * 1024f64.powi(power)) as u64)
+}
+
+/// Name next to path to write under before renaming into place
+pub fn temp_path(path: &Path) -> Result<PathBuf> {
+    let mut tmp = PathBuf::from(path);
+    let mut name = path.file_name().with_context(|| format!("not a file path: {}", path.display()))?.to_os_string();
+    name.push(format!(".tmp.{}", std::process::id()));
+    tmp.set_file_name(name);
+    Ok(tmp)
+}
+
+/// Buffered stdout, or a file written under a temp name that is renamed
+/// into place by finish so readers never see a partial file
+pub struct Output {
+    w: BufWriter<Box<dyn Write + Send>>,
+    rename: Option<(PathBuf, PathBuf)>,
+}
+
+impl Output {
+    pub fn new(path: Option<&Path>) -> Result<Output> {
+        let path = match path {
+            None => return Ok(Output { w: BufWriter::new(Box::new(std::io::stdout())), rename: None }),
+            Some(p) => p,
+        };
+        let tmp = temp_path(path)?;
+        let file = File::create(&tmp).with_context(|| format!("cannot create temp file: {}", tmp.display()))?;
+        Ok(Output { w: BufWriter::new(Box::new(file)), rename: Some((tmp, path.to_path_buf())) })
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.w.flush()?;
+        if let Some((tmp, path)) = self.rename.take() {
+            rename(&tmp, &path).with_context(|| format!("cannot rename {} to {}", tmp.display(), path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.w.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.w.flush()
     }
+}
 
-    let mut s = format!("{}", number);
-    s.truncate(4);
-    if s.ends_with('.') {
-        s.pop();
+impl Drop for Output {
+    // never finished so something failed - leave no temp file behind
+    fn drop(&mut self) {
+        if let Some((tmp, _)) = &self.rename {
+            let _ = std::fs::remove_file(tmp);
+        }
     }
-    if s.len() < 4 { s.push(' '); }
+}
+
+/// Write a file through a temp file in the same directory and rename it into
+/// place so readers never see a partial file
+pub fn write_atomic<F>(path: &Path, f: F) -> Result<()>
+    where F: FnOnce(&mut dyn Write) -> Result<()>
+{
+    let mut out = Output::new(Some(path))?;
+    f(&mut out).with_context(|| format!("writing: {}", path.display()))?;
+    out.finish()
+}
+
+// the fields only unix has: blocks, uid, gid, mode, ctime in microseconds, inode and nlink
+#[cfg(target_family = "unix")]
+pub fn unix_fields(md: &Metadata) -> (i64, i64, i64, i32, Option<i64>, i64, i64) {
+    let ctime = md.ctime() * 1_000_000 + md.ctime_nsec() / 1000;
+    (md.blocks() as i64, md.uid() as i64, md.gid() as i64, md.mode() as i32, Some(ctime), md.ino() as i64, md.nlink() as i64)
+}
 
-    return format!("{:<5}{}", s, GREEK_SUFFIXES[multi]);
+#[cfg(target_family = "windows")]
+pub fn unix_fields(_md: &Metadata) -> (i64, i64, i64, i32, Option<i64>, i64, i64) {
+    (0, 0, 0, 0, None, 0, 0)
+}
+
+/// Microseconds since the epoch, negative before it and None when the
+/// platform does not keep that time
+pub fn micros(t: std::io::Result<SystemTime>) -> Option<i64> {
+    let t = t.ok()?;
+    Some(match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_micros() as i64,
+        Err(e) => -(e.duration().as_micros() as i64),
+    })
 }
 
 #[cfg(target_os = "windows")]
@@ -107,3 +347,171 @@ pub fn gettid() -> usize {
 pub fn gettid() -> usize {
     unsafe { libc::syscall(libc::SYS_gettid) as usize }
 }
+
+/// Directory for a test's files, removed when dropped so a failed assert
+/// does not leave it behind
+#[cfg(test)]
+pub struct ScratchDir(PathBuf);
+
+#[cfg(test)]
+impl ScratchDir {
+    pub fn new() -> ScratchDir {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static MADE: AtomicUsize = AtomicUsize::new(0);
+        // create_dir fails on a name already taken, by this run or another
+        loop {
+            let dir = std::env::temp_dir().join(format!("du2-test-{}-{}", std::process::id(), MADE.fetch_add(1, Ordering::Relaxed)));
+            match std::fs::create_dir(&dir) {
+                Ok(()) => return ScratchDir(dir),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => panic!("cannot create {}: {}", dir.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn glob(g: &str, s: &str) -> bool {
+        Regex::new(&glob_to_re(g)).unwrap().is_match(s)
+    }
+
+    #[test]
+    fn glob_star_stays_in_one_name() {
+        assert!(glob("*.log", "a.log"));
+        assert!(!glob("*.log", "dir/a.log"));
+        assert!(glob("*/*.log", "dir/a.log"));
+        assert!(glob("**.log", "dir/sub/a.log"));
+        assert!(glob("home/**", "home/a/b"));
+        assert!(!glob("*.log", "a.log.1"));
+    }
+
+    #[test]
+    fn glob_leading_dot_must_be_spelled_out() {
+        assert!(!glob("*", ".hidden"));
+        assert!(!glob("?hidden", ".hidden"));
+        assert!(glob(".*", ".hidden"));
+        assert!(!glob("*", "."));
+        assert!(glob("a/.*", "a/.git"));
+    }
+
+    #[test]
+    fn glob_classes_and_literals() {
+        assert!(glob("*.log.[0-9]*", "x.log.12"));
+        assert!(!glob("*.log.[0-9]*", "x.log.old"));
+        assert!(glob("[!a]*", "bc"));
+        assert!(!glob("[!a]*", "ab"));
+        assert!(glob("a[b", "a[b"));
+        assert!(glob("a+(b)", "a+(b)"));
+        assert!(!glob("a.b", "axb"));
+    }
+
+    #[test]
+    fn show_relative_and_rewritten() {
+        let dir = Path::new("/data/proj");
+        let rw = (PathBuf::from("/data"), "/mnt/nfs/".to_string());
+        assert_eq!(show_as(Path::new("/data/proj/a/b"), dir, true, None), "a/b");
+        assert_eq!(show_as(Path::new("/data/proj"), dir, true, None), ".");
+        assert_eq!(show_as(Path::new("/other/x"), dir, true, None), "/other/x");
+        assert_eq!(show_as(Path::new("/data/proj/a"), dir, false, Some(&rw)), "/mnt/nfs/proj/a");
+        assert_eq!(show_as(Path::new("/data"), dir, false, Some(&rw)), "/mnt/nfs/");
+        // a prefix is whole path components
+        assert_eq!(show_as(Path::new("/database/x"), dir, false, Some(&rw)), "/database/x");
+        assert_eq!(show_as(Path::new("/data/proj/a"), dir, false, None), "/data/proj/a");
+    }
+
+    #[test]
+    fn unshow_maps_back_to_the_scanned_path() {
+        let dir = Path::new("/data/proj");
+        let rw = (PathBuf::from("/data"), "/mnt/nfs/".to_string());
+        for p in ["/data/proj/a", "/data", "/data/proj"] {
+            let p = Path::new(p);
+            assert_eq!(unshow_as(&show_as(p, dir, false, Some(&rw)), dir, Some(&rw)), p);
+            assert_eq!(unshow_as(&show_as(p, dir, true, None), dir, None), p);
+        }
+        assert_eq!(unshow_as("./a/b", dir, None), Path::new("/data/proj/a/b"));
+        assert_eq!(unshow_as("", dir, None), dir);
+        assert_eq!(unshow_as("/mnt/nfsx", dir, Some(&rw)), Path::new("/mnt/nfsx"));
+    }
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("1500").unwrap(), 1500);
+        assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("2.5g").unwrap(), 5 * 1024 * 1024 * 1024 / 2);
+        assert_eq!(parse_size("1TB").unwrap(), 1 << 40);
+        assert_eq!(parse_size(" 3 MiB ").unwrap(), 3 << 20);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("12Q").is_err());
+        assert!(parse_size("K").is_err());
+    }
+
+    #[test]
+    fn units_by_name() {
+        assert_eq!("iec".parse::<Units>().unwrap(), Units::Iec);
+        assert_eq!("si".parse::<Units>().unwrap(), Units::Si);
+        assert_eq!("bytes".parse::<Units>().unwrap(), Units::Bytes);
+        assert!("blocks".parse::<Units>().is_err());
+        assert_eq!(Units::default(), Units::Iec);
+    }
+
+    #[test]
+    fn fmt_size_per_units() {
+        assert_eq!(fmt_size_as(999, Units::Iec), "999  B");
+        assert_eq!(fmt_size_as(1536, Units::Iec), "1.50 KiB");
+        assert_eq!(fmt_size_as(150 << 20, Units::Iec), "150  MiB");
+        assert_eq!(fmt_size_as(1500, Units::Si), "1.50 kB");
+        assert_eq!(fmt_size_as(1_234_567, Units::Bytes), "1234567");
+        assert_eq!(fmt_size_as(1 << 20, Units::Block(1024)), "1,024 KiB");
+        assert_eq!(fmt_size_as(1, Units::Block(1000)), "1 kB");
+        assert_eq!(fmt_size_as(4097, Units::Block(4096)), "2 4K-blocks");
+        assert_eq!(fmt_size_as(3000, Units::Block(1536)), "2 1536-blocks");
+        assert_eq!(fmt_count_as(1_234_567u64, Units::Iec), "1,234,567");
+        assert_eq!(fmt_count_as(1_234_567u64, Units::Bytes), "1234567");
+    }
+
+    #[test]
+    fn block_labels() {
+        assert_eq!(block_label(1), "B");
+        assert_eq!(block_label(1 << 30), "GiB");
+        assert_eq!(block_label(1_000_000), "MB");
+        assert_eq!(block_label(8 << 20), "8M-blocks");
+        assert_eq!(block_label(512), "512-blocks");
+    }
+
+    #[test]
+    fn multi_extension_keeps_long_and_compound() {
+        let ext = |s: &str| multi_extension(Path::new(s)).map(|x| x.into_owned());
+        assert_eq!(ext("model.safetensors").as_deref(), Some(".safetensors"));
+        assert_eq!(ext("/data/x.parquet").as_deref(), Some(".parquet"));
+        assert_eq!(ext("backup.tar.zst").as_deref(), Some(".tar.zst"));
+        assert_eq!(ext("data.csv.GZ").as_deref(), Some(".csv.GZ"));
+        assert_eq!(ext("data.jsonl.gz").as_deref(), Some(".jsonl.gz"));
+        assert_eq!(ext("x.parquet.zst").as_deref(), Some(".parquet.zst"));
+        assert_eq!(ext("v1.2.gz").as_deref(), Some(".2.gz"));
+        assert_eq!(ext("release-1.0.tar").as_deref(), Some(".tar"));
+        assert_eq!(ext("a.b/Makefile"), None);
+        assert_eq!(ext(".bashrc"), None);
+        assert_eq!(ext("notes."), None);
+        assert_eq!(ext("Meeting notes. final"), None);
+    }
+}
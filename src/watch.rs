@@ -0,0 +1,259 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+use crate::cli::{APP, EXE};
+use crate::{list_dir, track_dir_tops, AllStats, DirStats, MetaList};
+
+/// Why watching stopped and a full scan is needed to carry on
+pub enum WatchEnd {
+    /// the kernel dropped events so the tree can no longer be trusted
+    Resync,
+    /// there are more directories than inotify watches allowed
+    LimitReached,
+}
+
+// time to let a burst of changes settle before applying them
+const SETTLE: Duration = Duration::from_secs(1);
+
+struct Watches {
+    ino: Inotify,
+    paths: HashMap<WatchDescriptor, PathBuf>,
+    by_path: HashMap<PathBuf, WatchDescriptor>,
+}
+
+impl Watches {
+    // false when the watch limit has been hit
+    fn add(&mut self, p: &Path) -> Result<bool> {
+        let mask = WatchMask::CREATE | WatchMask::DELETE | WatchMask::MODIFY
+            | WatchMask::MOVED_FROM | WatchMask::MOVED_TO | WatchMask::ONLYDIR;
+        match self.ino.watches().add(p, mask) {
+            Ok(wd) => {
+                // a directory moved within the tree keeps its descriptor
+                self.paths.insert(wd.clone(), p.to_path_buf());
+                self.by_path.insert(p.to_path_buf(), wd);
+                Ok(true)
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ENOSPC) => {
+                eprintln!("{}: inotify watch limit reached at {} directories - see fs.inotify.max_user_watches",
+                          *EXE, self.paths.len());
+                Ok(false)
+            }
+            Err(e) => {
+                // gone already or not readable - its parent's events still count it
+                if APP.verbose > 0 {
+                    eprintln!("{}: cannot watch '{}', error: {}", *EXE, p.display(), e);
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    fn remove(&mut self, p: &Path) {
+        if let Some(wd) = self.by_path.remove(p) {
+            if self.paths.get(&wd).is_some_and(|x| x == p) {
+                self.paths.remove(&wd);
+                // fails when the kernel already dropped it for a deleted directory
+                let _ = self.ino.watches().remove(wd);
+            }
+        }
+    }
+}
+
+/// Keep the directory totals of a finished scan current from inotify events
+///
+/// Only the directory tree and total usage are maintained - per user,
+/// extension and the other sections stay as of the last full scan.
+/// `updated` is called with the number of directories re-read after each
+/// batch of changes, once the top N lists are recomputed.
+pub fn watch(stats: &Arc<RwLock<AllStats>>, updated: &mut dyn FnMut(&AllStats, usize) -> Result<()>) -> Result<WatchEnd> {
+    let mut w = Watches {
+        ino: Inotify::init().context("cannot start inotify")?,
+        paths: HashMap::new(),
+        by_path: HashMap::new(),
+    };
+    let dirs: Vec<PathBuf> = {
+        let s = stats.read().unwrap();
+        std::iter::once(APP.dir.clone()).chain(s.dtree.keys().cloned()).collect()
+    };
+    for d in dirs.iter() {
+        if !w.add(d)? {
+            return Ok(WatchEnd::LimitReached);
+        }
+    }
+    if APP.verbose > 0 {
+        eprintln!("{}: watching {} directories", *EXE, w.paths.len());
+    }
+
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let mut dirty = BTreeSet::new();
+        let mut overflow = false;
+        for ev in w.ino.read_events_blocking(&mut buf)? {
+            overflow |= note(&w, &ev.wd, ev.mask, &mut dirty);
+        }
+        thread::sleep(SETTLE);
+        loop {
+            match w.ino.read_events(&mut buf) {
+                Ok(evs) => {
+                    let mut n = 0;
+                    for ev in evs {
+                        overflow |= note(&w, &ev.wd, ev.mask, &mut dirty);
+                        n += 1;
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if overflow {
+            eprintln!("{}: inotify queue overflowed - rescanning", *EXE);
+            return Ok(WatchEnd::Resync);
+        }
+        if dirty.is_empty() {
+            continue;
+        }
+
+        let mut s = stats.write().unwrap();
+        let mut added = vec![];
+        for d in dirty.iter() {
+            // skip what went away with a parent handled before it
+            if s.dtree.contains_key(d) && d.exists() {
+                added.extend(update_dir(&mut s, &mut w, d)?);
+            }
+        }
+        let mut reread = dirty.len();
+        for d in added {
+            match add_tree(&mut s, &mut w, &d)? {
+                None => return Ok(WatchEnd::LimitReached),
+                Some(n) => reread += n,
+            }
+        }
+        track_dir_tops(&mut s);
+        updated(&s, reread)?;
+    }
+}
+
+// true when the event means changes were lost
+fn note(w: &Watches, wd: &WatchDescriptor, mask: EventMask, dirty: &mut BTreeSet<PathBuf>) -> bool {
+    if mask.contains(EventMask::Q_OVERFLOW) {
+        return true;
+    }
+    if !mask.contains(EventMask::IGNORED) {
+        if let Some(p) = w.paths.get(wd) {
+            dirty.insert(p.clone());
+        }
+    }
+    false
+}
+
+// size, files and dirs right under a directory, counted as perk_up_disk_usage does
+fn direct_stats(list: &MetaList) -> Result<(u64, u64, u64)> {
+    let mut d = (0, 0, 0);
+    for afile in list {
+        let filetype = afile.1.file_type();
+        if !filetype.is_file() && !filetype.is_dir() {
+            continue;
+        }
+        let f_age = afile.1.modified()?;
+        if APP.file_newer_than.is_none_or(|x| x < f_age) && APP.file_older_than.is_none_or(|x| x > f_age) {
+            d.0 += afile.1.len();
+            if filetype.is_file() {
+                d.1 += 1;
+            } else {
+                d.2 += 1;
+            }
+        }
+    }
+    Ok(d)
+}
+
+// add a change to a directory's recursive totals and those of all above it
+fn apply_delta(stats: &mut AllStats, dir: &Path, size: u64, files: u64, dirs: u64) {
+    let mut p = dir;
+    loop {
+        let ds = stats.dtree.entry(p.to_path_buf()).or_insert_with(DirStats::new);
+        ds.size_recursively = ds.size_recursively.wrapping_add(size);
+        ds.file_count_recursively = ds.file_count_recursively.wrapping_add(files);
+        ds.dir_count_recursively = ds.dir_count_recursively.wrapping_add(dirs);
+        if p == APP.dir {
+            break;
+        }
+        match p.parent() {
+            Some(par) if par != p => p = par,
+            _ => break,
+        }
+    }
+}
+
+// set a directory's direct totals from a fresh listing and carry the difference up
+fn set_direct(stats: &mut AllStats, dir: &Path, new: (u64, u64, u64)) {
+    let ds = stats.dtree.entry(dir.to_path_buf()).or_insert_with(DirStats::new);
+    let old = (ds.size_directly, ds.file_count_directly, ds.dir_count_directly);
+    ds.size_directly = new.0;
+    ds.file_count_directly = new.1;
+    ds.dir_count_directly = new.2;
+    stats.total_usage = stats.total_usage.wrapping_add(new.0.wrapping_sub(old.0));
+    apply_delta(stats, dir, new.0.wrapping_sub(old.0), new.1.wrapping_sub(old.1), new.2.wrapping_sub(old.2));
+}
+
+// re-read one directory, dropping sub directories that are gone and
+// returning the new ones still to be walked
+fn update_dir(stats: &mut AllStats, w: &mut Watches, dir: &Path) -> Result<Vec<PathBuf>> {
+    let (list, subdirs) = match list_dir(dir)? {
+        None => return Ok(vec![]),
+        Some(x) => x,
+    };
+    set_direct(stats, dir, direct_stats(&list)?);
+
+    let now: BTreeSet<PathBuf> = subdirs.into_iter().collect();
+    let before: Vec<PathBuf> = stats.dtree.range(dir.to_path_buf()..)
+        .skip(1)
+        .take_while(|(k, _)| k.starts_with(dir))
+        .filter(|(k, _)| k.parent() == Some(dir))
+        .map(|(k, _)| k.clone())
+        .collect();
+    for gone in before.iter().filter(|k| !now.contains(*k)) {
+        let ds = stats.dtree[gone].clone();
+        apply_delta(stats, dir, ds.size_recursively.wrapping_neg(),
+                    ds.file_count_recursively.wrapping_neg(), ds.dir_count_recursively.wrapping_neg());
+        stats.total_usage = stats.total_usage.wrapping_sub(ds.size_recursively);
+        let subtree: Vec<PathBuf> = stats.dtree.range(gone.clone()..)
+            .take_while(|(k, _)| k.starts_with(gone))
+            .map(|(k, _)| k.clone())
+            .collect();
+        for k in subtree {
+            stats.dtree.remove(&k);
+            w.remove(&k);
+        }
+    }
+    Ok(now.into_iter().filter(|k| !stats.dtree.contains_key(k)).collect())
+}
+
+// read and watch a directory new to the tree and everything below it -
+// the number of directories read or None when out of watches
+fn add_tree(stats: &mut AllStats, w: &mut Watches, top: &Path) -> Result<Option<usize>> {
+    let mut todo = vec![top.to_path_buf()];
+    let mut n = 0;
+    while let Some(d) = todo.pop() {
+        if !w.add(&d)? {
+            return Ok(None);
+        }
+        stats.dtree.entry(d.clone()).or_insert_with(DirStats::new);
+        if let Some((list, subdirs)) = list_dir(&d)? {
+            set_direct(stats, &d, direct_stats(&list)?);
+            todo.extend(subdirs);
+        }
+        n += 1;
+    }
+    Ok(Some(n))
+}
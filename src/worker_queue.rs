@@ -79,7 +79,7 @@ impl<T> WorkerQueue<T> {
     pub fn pop(&mut self) -> T {
         let mut lck_q = self.tqueue.lock().unwrap();
         lck_q.curr_poppers += 1;
-        while lck_q.queue.len() < 1 {
+        while lck_q.queue.is_empty() {
             if lck_q.curr_poppers == lck_q.max_waiters {
                 self.looks_done.notify_one();
             }
@@ -99,7 +99,7 @@ impl<T> WorkerQueue<T> {
         let ret = {
             let mut lck_q = self.tqueue.lock().unwrap();
             // sanity check because we have more new work than the queue can hold
-            while !(lck_q.queue.len() <= 0 && lck_q.curr_poppers == lck_q.max_waiters) {
+            while !(lck_q.queue.is_empty() && lck_q.curr_poppers == lck_q.max_waiters) {
                 let x = self.looks_done.wait_timeout(lck_q, dur).unwrap();
                 lck_q = x.0;
                 if x.1.timed_out() {
@@ -124,7 +124,7 @@ impl<T> WorkerQueue<T> {
         if lck_q.limit > 0 && lck_q.curr_pushers >= lck_q.max_waiters && lck_q.queue.len() >= lck_q.limit {
             Err(anyhow!("Queue looks stuck at limit {} and waiters {}", &lck_q.queue.len(), &lck_q.curr_poppers))?;
         }
-        while !(lck_q.queue.len() <= 0 && lck_q.curr_poppers >= lck_q.max_waiters) {
+        while !(lck_q.queue.is_empty() && lck_q.curr_poppers >= lck_q.max_waiters) {
             lck_q = self.looks_done.wait(lck_q).unwrap();
         }
         Ok(lck_q.curr_poppers)